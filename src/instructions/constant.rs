@@ -0,0 +1,81 @@
+use std::fmt;
+
+use super::Token;
+use super::TokenKind;
+
+/// A `C'...'` or `X'...'` constant of a BYTE operand or literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Constant {
+    Chars(String),
+    Hex(String),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Chars(chars) => write!(f, "C'{}'", chars),
+            Constant::Hex(hex) => write!(f, "X'{}'", hex),
+        }
+    }
+}
+
+impl Constant {
+    /// Parses a constant token. Hex constants need an even number of hex digits.
+    pub fn new(token: &Token) -> Option<Self> {
+        if token.kind != TokenKind::Constant {
+            return None;
+        }
+
+        let contents = &token.text[2..token.text.len() - 1];
+        match &token.text[..1] {
+            "C" | "c" => Some(Constant::Chars(contents.to_owned())),
+            _ if contents.len().is_multiple_of(2)
+                && contents.chars().all(|c| c.is_ascii_hexdigit()) =>
+            {
+                Some(Constant::Hex(contents.to_ascii_uppercase()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Size in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            Constant::Chars(chars) => chars.len(),
+            Constant::Hex(hex) => hex.len() / 2,
+        }
+    }
+
+    pub fn objcode(&self) -> String {
+        match self {
+            Constant::Chars(chars) => chars.bytes().map(|c| format!("{:02X}", c)).collect(),
+            Constant::Hex(hex) => hex.to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::tokenize;
+
+    #[test]
+    fn test_constant() {
+        let constant = |text| Constant::new(&tokenize(text).unwrap()[0]);
+
+        let chars = constant("C'E O F'").unwrap();
+        assert_eq!(format!("{}", chars), "C'E O F'");
+        assert_eq!(
+            (chars.size(), chars.objcode()),
+            (5, "45204F2046".to_owned())
+        );
+
+        let hex = constant("X'f1'").unwrap();
+        assert_eq!(format!("{}", hex), "X'F1'");
+        assert_eq!((hex.size(), hex.objcode()), (1, "F1".to_owned()));
+
+        assert_eq!(constant("X'F'"), None);
+        assert_eq!(constant("X'0G'"), None);
+        assert_eq!(constant("EOF"), None);
+    }
+}
@@ -0,0 +1,35 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub struct ControlSection {
+    pub name: String,
+    pub addr: usize,
+    pub len: usize,
+}
+
+impl fmt::Display for ControlSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "H{:<6}{:06X}{:06X}", self.name, self.addr, self.len)
+    }
+}
+
+impl ControlSection {
+    pub fn new(name: impl Into<String>, addr: usize, len: usize) -> Self {
+        Self {
+            name: name.into(),
+            addr,
+            len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_control_section() {
+        let section = ControlSection::new("COPY", 0, 0x1077);
+        assert_eq!(format!("{}", section), "HCOPY  000000001077");
+    }
+}
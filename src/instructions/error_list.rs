@@ -0,0 +1,46 @@
+use std::fmt;
+
+/// Every error found in a run, stage by stage and in source order within each. A stage
+/// reports each bad line it finds, leaves it out and goes on with the rest, so a single run
+/// reports all of them.
+#[derive(Debug)]
+pub struct ErrorList {
+    pub errors: Vec<anyhow::Error>,
+}
+
+impl fmt::Display for ErrorList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ErrorList {}
+
+impl ErrorList {
+    pub fn new(errors: Vec<anyhow::Error>) -> Self {
+        Self { errors }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_list() {
+        let errors = ErrorList::new(vec![
+            anyhow::anyhow!("error: invalid opcode\n3 | LDZ"),
+            anyhow::anyhow!("error: undefined symbol `BUF`\n7 | STA"),
+        ]);
+        assert_eq!(
+            format!("{}", errors),
+            "error: invalid opcode\n3 | LDZ\nerror: undefined symbol `BUF`\n7 | STA"
+        );
+    }
+}
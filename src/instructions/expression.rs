@@ -0,0 +1,241 @@
+use std::{iter::Peekable, slice::Iter};
+
+use super::SymbolKind;
+use super::SymbolMapping;
+use super::Token;
+use super::TokenKind;
+
+/// Value of an expression and the number of relative terms in it, where subtracted
+/// terms count negatively, so `BUFEND-BUFFER` is absolute. External references are
+/// left to the loader and kept with their sign, as in `+BUFEND` and `-BUFFER`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Value {
+    pub value: isize,
+    pub relative: isize,
+    pub externals: Vec<String>,
+}
+
+impl Value {
+    pub fn new(value: isize, relative: isize) -> Self {
+        Self {
+            value,
+            relative,
+            externals: Vec::new(),
+        }
+    }
+
+    fn negate(self) -> anyhow::Result<Self> {
+        Ok(Self {
+            value: self.value.checked_neg().ok_or_else(overflow)?,
+            relative: -self.relative,
+            externals: self.externals.iter().map(|ext| flip_sign(ext)).collect(),
+        })
+    }
+
+    pub fn kind(&self) -> SymbolKind {
+        if !self.externals.is_empty() {
+            SymbolKind::External
+        } else if self.relative == 0 {
+            SymbolKind::Absolute
+        } else {
+            SymbolKind::Relative
+        }
+    }
+}
+
+/// Evaluates the tokens of an expression of `+`, `-`, `*`, `/` and parentheses over decimal
+/// numbers, `X'...'` hex constants, previously defined symbols and `*` for the current
+/// location `addr`. The result has to be absolute or relative, and relative terms may only
+/// be added or subtracted.
+pub fn evaluate(tokens: &[Token], addr: usize, symtab: &[SymbolMapping]) -> anyhow::Result<Value> {
+    let expr = tokens
+        .iter()
+        .map(|token| token.text.as_str())
+        .collect::<String>();
+    let mut parser = Parser {
+        tokens: tokens.iter().peekable(),
+        addr,
+        symtab,
+    };
+
+    let value = parser.expression()?;
+
+    if let Some(token) = parser.tokens.next() {
+        return Err(anyhow::anyhow!(
+            "unexpected `{}` in expression `{}`",
+            token,
+            expr
+        ));
+    }
+
+    if !(0..=1).contains(&value.relative) {
+        return Err(anyhow::anyhow!("invalid relative expression `{}`", expr));
+    }
+
+    Ok(value)
+}
+
+fn overflow() -> anyhow::Error {
+    anyhow::anyhow!("arithmetic overflow in expression")
+}
+
+fn flip_sign(external: &str) -> String {
+    match external.strip_prefix('+') {
+        Some(symbol) => format!("-{}", symbol),
+        None => format!("+{}", &external[1..]),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Peekable<Iter<'a, Token>>,
+    addr: usize,
+    symtab: &'a [SymbolMapping],
+}
+
+impl Parser<'_> {
+    /// Takes the next token if it is one of the operators `ops`.
+    fn operator(&mut self, ops: &[&str]) -> Option<String> {
+        self.tokens
+            .next_if(|token| {
+                token.kind == TokenKind::Operator && ops.contains(&token.text.as_str())
+            })
+            .map(|token| token.text.clone())
+    }
+
+    fn expression(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.term()?;
+
+        while let Some(op) = self.operator(&["+", "-"]) {
+            let rhs = if op == "+" {
+                self.term()?
+            } else {
+                self.term()?.negate()?
+            };
+
+            value.value = value.value.checked_add(rhs.value).ok_or_else(overflow)?;
+            value.relative += rhs.relative;
+            value.externals.extend(rhs.externals);
+        }
+
+        Ok(value)
+    }
+
+    fn term(&mut self) -> anyhow::Result<Value> {
+        let mut value = self.factor()?;
+
+        while let Some(op) = self.operator(&["*", "/"]) {
+            let rhs = self.factor()?;
+
+            if value.kind() != SymbolKind::Absolute || rhs.kind() != SymbolKind::Absolute {
+                return Err(anyhow::anyhow!("relative term used with `{}`", op));
+            }
+
+            let result = if op == "*" {
+                value.value.checked_mul(rhs.value)
+            } else if rhs.value != 0 {
+                value.value.checked_div(rhs.value)
+            } else {
+                return Err(anyhow::anyhow!("division by zero"));
+            };
+            value = Value::new(result.ok_or_else(overflow)?, 0);
+        }
+
+        Ok(value)
+    }
+
+    fn factor(&mut self) -> anyhow::Result<Value> {
+        let Some(token) = self.tokens.next() else {
+            return Err(anyhow::anyhow!("missing term in expression"));
+        };
+
+        match (token.kind, token.text.as_str()) {
+            (TokenKind::Operator, "(") => {
+                let value = self.expression()?;
+                match self.operator(&[")"]) {
+                    Some(_) => Ok(value),
+                    None => Err(anyhow::anyhow!("missing `)` in expression")),
+                }
+            }
+            (TokenKind::Operator, "-") => self.factor()?.negate(),
+            (TokenKind::Operator, "*") => Ok(Value::new(self.addr as isize, 1)),
+            (TokenKind::Number, number) => number
+                .parse()
+                .map(|value| Value::new(value, 0))
+                .map_err(|_| anyhow::anyhow!("invalid number `{}`", number)),
+            (TokenKind::Symbol, number) if number.starts_with(|c: char| c.is_ascii_digit()) => {
+                Err(anyhow::anyhow!("invalid number `{}`", number))
+            }
+            (TokenKind::Symbol, symbol) => {
+                match self.symtab.iter().find(|sym| sym.symbol == symbol) {
+                    Some(sym) => match sym.kind {
+                        SymbolKind::Absolute => Ok(Value::new(sym.addr as isize, 0)),
+                        SymbolKind::Relative => Ok(Value::new(sym.addr as isize, 1)),
+                        SymbolKind::External => Ok(Value {
+                            value: 0,
+                            relative: 0,
+                            externals: vec![format!("+{}", symbol)],
+                        }),
+                    },
+                    None => Err(anyhow::anyhow!("undefined symbol `{}`", symbol)),
+                }
+            }
+            (TokenKind::Constant, constant) if constant.starts_with(['X', 'x']) => {
+                let hex = &constant[2..constant.len() - 1];
+                isize::from_str_radix(hex, 16)
+                    .map(|value| Value::new(value, 0))
+                    .map_err(|_| anyhow::anyhow!("invalid hex constant `{}`", constant))
+            }
+            _ => Err(anyhow::anyhow!("unexpected `{}` in expression", token)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::tokenize;
+
+    #[test]
+    fn test_evaluate() {
+        let symtab = vec![
+            SymbolMapping::new("BUFFER", 0x0036),
+            SymbolMapping::new("BUFEND", 0x1036),
+            SymbolMapping::with_kind("MAXLEN", 4096, SymbolKind::Absolute),
+            SymbolMapping::with_kind("EXTBUF", 0, SymbolKind::External),
+            SymbolMapping::with_kind("EXTEND", 0, SymbolKind::External),
+        ];
+
+        assert_eq!(
+            evaluate(&tokenize("4096").unwrap(), 0, &symtab).unwrap(),
+            Value::new(4096, 0)
+        );
+        assert_eq!(
+            evaluate(&tokenize("*").unwrap(), 0x10, &symtab).unwrap(),
+            Value::new(0x10, 1)
+        );
+        assert_eq!(
+            evaluate(&tokenize("BUFEND-BUFFER").unwrap(), 0, &symtab).unwrap(),
+            Value::new(0x1000, 0)
+        );
+        assert_eq!(
+            evaluate(&tokenize("BUFFER+MAXLEN").unwrap(), 0, &symtab).unwrap(),
+            Value::new(0x1036, 1)
+        );
+        assert_eq!(
+            evaluate(&tokenize("(BUFEND-BUFFER)/3*2+X'10'").unwrap(), 0, &symtab).unwrap(),
+            Value::new(0x1000 / 3 * 2 + 0x10, 0)
+        );
+        assert!(evaluate(&tokenize("BUFFER*2").unwrap(), 0, &symtab).is_err());
+        assert!(evaluate(&tokenize("BUFFER+BUFEND").unwrap(), 0, &symtab).is_err());
+        assert_eq!(
+            evaluate(&tokenize("EXTEND-EXTBUF").unwrap(), 0, &symtab)
+                .unwrap()
+                .externals,
+            vec!["+EXTEND", "-EXTBUF"]
+        );
+        assert!(evaluate(&tokenize("UNDEF").unwrap(), 0, &symtab).is_err());
+        assert!(evaluate(&tokenize("99999999999*99999999999").unwrap(), 0, &symtab).is_err());
+        assert!(evaluate(&tokenize("9223372036854775807+1").unwrap(), 0, &symtab).is_err());
+        assert!(evaluate(&tokenize("-9223372036854775807-2").unwrap(), 0, &symtab).is_err());
+    }
+}
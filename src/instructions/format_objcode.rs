@@ -1,70 +1,144 @@
-use super::InstructionType;
+use super::symbol_list;
+use super::ControlSection;
+use super::Modification;
+use super::Statement;
+use super::SymbolMapping;
 
-pub fn format_objcode(instructions: &Vec<InstructionType>, objcodes: Vec<String>) -> Vec<String> {
+pub fn format_objcode(
+    instructions: &[Statement],
+    objcodes: Vec<String>,
+    modifications: &[Vec<Modification>],
+    symtab: &[SymbolMapping],
+    sections: &[ControlSection],
+) -> Vec<String> {
     let mut lines = Vec::new();
 
-    let mut pname = String::new();
-    let mut paddr = 0;
+    let mut section = 0;
+    let mut header = 0;
+    let mut extdefs = Vec::new();
+    let mut extrefs = Vec::new();
+    let mut start = 0;
     let mut temp = String::new();
     for (ins, objcode) in instructions.iter().zip(objcodes) {
-        match ins {
-            InstructionType::SymbolOpcodeOperand(ins) => match ins.opcode.as_str() {
-                "START" => {
-                    pname = ins.symbol.to_owned();
-                    paddr = ins.addr;
+        match ins.opcode.as_str() {
+            "CSECT" | "END" if section < sections.len() => {
+                if !temp.is_empty() {
+                    lines.push(text_record(start, &temp));
+                    temp.clear();
                 }
-                "RESB" | "RESW" => {
-                    if !temp.is_empty() {
-                        let len = temp.len() >> 1;
-                        lines.push(format!("T{:06X}{:02X}{}", ins.addr - len, len, temp));
-                    }
-                    temp = objcode;
+
+                // H, D and R records go in front of the section's T records
+                let mut records = vec![format!("{}", sections[section])];
+                for chunk in extdefs.chunks(5) {
+                    records.push(define_record(chunk, symtab, section));
                 }
-                _ => {
-                    if temp.len() + objcode.len() > 60 {
-                        let len = temp.len() >> 1;
-                        lines.push(format!("T{:06X}{:02X}{}", ins.addr - len, len, temp));
-                        temp = objcode;
-                    } else {
-                        temp.push_str(&objcode);
-                    }
+                for chunk in extrefs.chunks(10) {
+                    records.push(refer_record(chunk));
                 }
-            },
+                lines.splice(header..header, records);
 
-            InstructionType::OpcodeOperand(ins) => match ins.opcode.as_str() {
-                "END" => {
-                    if !temp.is_empty() {
-                        let len = temp.len() >> 1;
-                        lines.push(format!("T{:06X}{:02X}{}", ins.addr - len, len, temp));
-                    }
-                    lines.insert(
-                        0,
-                        format!("H{:<6}{:06X}{:06X}", pname, paddr, ins.addr - paddr),
-                    );
-                    lines.push(format!("E{:06X}", paddr));
+                for modification in &modifications[section] {
+                    lines.push(format!("{}", modification));
                 }
-                _ => {
-                    if temp.len() + objcode.len() > 60 {
-                        let len = temp.len() >> 1;
-                        lines.push(format!("T{:06X}{:02X}{}", ins.addr - len, len, temp));
-                        temp = objcode;
-                    } else {
-                        temp.push_str(&objcode);
-                    }
+                if section == 0 {
+                    lines.push(format!("E{:06X}", sections[0].addr));
+                } else {
+                    lines.push("E".to_owned());
                 }
-            },
 
-            InstructionType::OpcodeOnly(ins) => {
-                if temp.len() + objcode.len() > 60 {
-                    let len = temp.len() >> 1;
-                    lines.push(format!("T{:06X}{:02X}{}", ins.addr - len, len, temp));
-                    temp = objcode;
-                } else {
-                    temp.push_str(&objcode);
+                section += 1;
+                header = lines.len();
+                extdefs.clear();
+                extrefs.clear();
+            }
+            "EXTDEF" => extdefs.extend(operands(ins)),
+            "EXTREF" => extrefs.extend(operands(ins)),
+            _ if objcode.is_empty() => {}
+            _ => {
+                // A record holds contiguous bytes only, so RESB/RESW or ORG start a new one
+                let contiguous = ins.addr == start + (temp.len() >> 1);
+                if !temp.is_empty() && (!contiguous || temp.len() + objcode.len() > 60) {
+                    lines.push(text_record(start, &temp));
+                    temp.clear();
+                }
+
+                if temp.is_empty() {
+                    start = ins.addr;
                 }
+                temp.push_str(&objcode);
             }
         }
     }
 
     lines
 }
+
+/// Symbols of an EXTDEF or EXTREF list, already checked by the passes.
+fn operands(ins: &Statement) -> Vec<&str> {
+    symbol_list(&ins.tokens).unwrap_or_default()
+}
+
+fn text_record(start: usize, objcode: &str) -> String {
+    format!("T{:06X}{:02X}{}", start, objcode.len() >> 1, objcode)
+}
+
+fn define_record(symbols: &[&str], symtab: &[SymbolMapping], section: usize) -> String {
+    let mut record = "D".to_owned();
+    for symbol in symbols {
+        let addr = symtab
+            .iter()
+            .find(|sym| sym.section == section && sym.symbol == *symbol)
+            .map_or(0, |sym| sym.addr);
+        record.push_str(&format!("{:<6}{:06X}", symbol, addr));
+    }
+    record
+}
+
+fn refer_record(symbols: &[&str]) -> String {
+    let mut record = "R".to_owned();
+    for symbol in symbols {
+        record.push_str(&format!("{:<6}", symbol));
+    }
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::{numbered, pass1, pass2, SourceFormat};
+
+    #[test]
+    fn test_format_objcode_location_jump() {
+        let source = [
+            "COPY\tSTART\t1000",
+            "\tLDA\t#1",
+            "\tORG\t*+X'100'",
+            "TAB\tBYTE\tX'F1'",
+            "\tORG",
+            "\tRSUB",
+            "BUF\tRESB\t3",
+            "\tWORD\t5",
+            "\tEND\tCOPY",
+        ];
+
+        let (instructions, symtab, sections, errors) = pass1(numbered(&source, SourceFormat::Free));
+        assert!(errors.is_empty());
+        let (objcodes, modifications, errors) = pass2(&instructions, &symtab);
+        assert!(errors.is_empty());
+        let objectcode =
+            format_objcode(&instructions, objcodes, &modifications, &symtab, &sections);
+
+        assert_eq!(
+            objectcode
+                .iter()
+                .filter(|line| line.starts_with('T'))
+                .collect::<Vec<_>>(),
+            vec![
+                "T00100003010001",
+                "T00110301F1",
+                "T001003034F0000",
+                "T00100903000005",
+            ]
+        );
+    }
+}
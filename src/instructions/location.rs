@@ -0,0 +1,43 @@
+use std::{fmt, rc::Rc};
+
+/// Where a statement comes from, a line of the input or of an included file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Location {
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}", file, self.line),
+            None => write!(f, "{}", self.line),
+        }
+    }
+}
+
+impl From<usize> for Location {
+    fn from(line: usize) -> Self {
+        Self { file: None, line }
+    }
+}
+
+impl Location {
+    pub fn new(file: Option<Rc<str>>, line: usize) -> Self {
+        Self { file, line }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_location() {
+        assert_eq!(format!("{}", Location::from(12)), "12");
+        assert_eq!(
+            format!("{}", Location::new(Some("io.asm".into()), 3)),
+            "io.asm:3"
+        );
+    }
+}
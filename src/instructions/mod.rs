@@ -1,19 +1,47 @@
+mod constant;
+mod control_section;
+mod error_list;
+mod expression;
 mod format_objcode;
-mod instruction_opcode_only;
-mod instruction_opcode_operand;
-mod instruction_symbol_opcode_operand;
-mod instruction_type;
+mod location;
+mod modification;
 mod opcode_map;
 mod pass1;
 mod pass2;
+mod register_map;
+mod source_format;
+mod statement;
 mod symbol_mapping;
+mod token;
+mod tokenize;
 
+pub use constant::Constant;
+pub use control_section::ControlSection;
+pub use error_list::ErrorList;
+pub(crate) use expression::evaluate;
+use expression::Value;
 pub use format_objcode::format_objcode;
-use instruction_opcode_only::InstructionOpcodeOnly;
-use instruction_opcode_operand::InstructionOpcodeOperand;
-use instruction_symbol_opcode_operand::InstructionSymbolOpcodeOperand;
-use instruction_type::InstructionType;
+pub use location::Location;
+pub use modification::Modification;
 use opcode_map::OPCODE_MAP;
+pub(crate) use pass1::is_mnemonic;
+#[cfg(test)]
+use pass1::numbered;
 pub use pass1::pass1;
+pub(crate) use pass1::statement_fields;
 pub use pass2::pass2;
-use symbol_mapping::SymbolMapping;
+use register_map::REGISTER_MAP;
+pub use source_format::SourceFormat;
+pub use statement::Statement;
+pub(crate) use symbol_mapping::SymbolKind;
+pub(crate) use symbol_mapping::SymbolMapping;
+pub use token::Token;
+pub use token::TokenKind;
+pub use tokenize::field_spans;
+pub use tokenize::fixed_statement;
+pub use tokenize::fold_case;
+pub use tokenize::split_columns;
+pub use tokenize::split_fields;
+use tokenize::split_operands;
+use tokenize::symbol_list;
+pub use tokenize::tokenize;
@@ -0,0 +1,50 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub struct Modification {
+    pub addr: usize,
+    pub len: usize,
+    pub symbol: Option<String>,
+}
+
+impl fmt::Display for Modification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.symbol {
+            Some(symbol) => write!(f, "M{:06X}{:02X}{}", self.addr, self.len, symbol),
+            None => write!(f, "M{:06X}{:02X}", self.addr, self.len),
+        }
+    }
+}
+
+impl Modification {
+    pub fn new(addr: usize, len: usize) -> Self {
+        Self {
+            addr,
+            len,
+            symbol: None,
+        }
+    }
+
+    /// Modification by the value of an external symbol, given with its sign as in `+RDREC`.
+    pub fn with_symbol(addr: usize, len: usize, symbol: impl Into<String>) -> Self {
+        Self {
+            addr,
+            len,
+            symbol: Some(symbol.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modification() {
+        let modification = Modification::new(0x0007, 5);
+        assert_eq!(format!("{}", modification), "M00000705");
+
+        let modification = Modification::with_symbol(0x0004, 5, "+RDREC");
+        assert_eq!(format!("{}", modification), "M00000405+RDREC");
+    }
+}
@@ -1,103 +1,929 @@
-use super::InstructionOpcodeOnly;
-use super::InstructionOpcodeOperand;
-use super::InstructionSymbolOpcodeOperand;
-use super::InstructionType;
+use super::evaluate;
+use super::field_spans;
+use super::fixed_statement;
+use super::fold_case;
+use super::split_columns;
+use super::split_fields;
+use super::symbol_list;
+use super::tokenize;
+use super::Constant;
+use super::ControlSection;
+use super::Location;
+use super::SourceFormat;
+use super::Statement;
+use super::SymbolKind;
 use super::SymbolMapping;
+use super::Token;
+use super::Value;
+use super::OPCODE_MAP;
 
+/// Assembler directives, as opposed to machine instructions.
+const DIRECTIVES: [&str; 15] = [
+    "START", "END", "BYTE", "WORD", "RESB", "RESW", "BASE", "NOBASE", "LTORG", "EQU", "ORG", "USE",
+    "CSECT", "EXTDEF", "EXTREF",
+];
+
+/// Label, opcode and operand of a statement.
+type Fields<'a> = (Option<&'a str>, &'a str, Option<&'a str>);
+
+/// Assigns addresses to the statements of `source`, given with the format of each line,
+/// and builds the symbol table.
 pub fn pass1(
-    source: impl Iterator<Item = String>,
-) -> anyhow::Result<(Vec<InstructionType>, Vec<SymbolMapping>)> {
-    let mut source = source.map(|l| l.trim().to_uppercase());
+    source: impl Iterator<Item = (Location, String, SourceFormat)>,
+) -> (
+    Vec<Statement>,
+    Vec<SymbolMapping>,
+    Vec<ControlSection>,
+    Vec<anyhow::Error>,
+) {
+    let mut source = source.map(|(line_num, l, format)| match format {
+        SourceFormat::Free => (line_num, free_statement(&l).to_owned(), format),
+        SourceFormat::Fixed => (line_num, fixed_statement(&l).to_owned(), format),
+    });
 
     let mut instructions = Vec::new();
     let mut symtab = Vec::new();
 
     // Line 1
-    let mut addr = if let [symbol, "START", operand] = source
-        .next()
-        .unwrap_or_default()
-        .split_whitespace()
-        .collect::<Vec<_>>()[..]
-    {
-        let addr = usize::from_str_radix(&operand, 16)?;
-
-        instructions.push(InstructionType::SymbolOpcodeOperand(
-            InstructionSymbolOpcodeOperand::new(1, addr, symbol, "START", operand),
-        ));
-
-        addr
-    } else {
-        return Err(anyhow::anyhow!("Missing START instruction"));
+    let (line_num, line, format) = source.next().unwrap_or_default();
+    let (mut name, mut start) = match start_statement(&line_num, &line, format) {
+        Ok(ins) => {
+            let program = (ins.label.clone().unwrap_or_default(), ins.addr);
+            instructions.push(ins);
+            program
+        }
+        Err(error) => return (instructions, symtab, Vec::new(), vec![error]),
     };
 
     // Line 2+
-    for (line_num, line) in source.enumerate() {
-        if line.starts_with('.') {
-            continue;
-        }
+    let mut addr = start;
+    let mut literals = Vec::new();
+    let mut saved_addr = None;
 
-        if addr > 0x7fff {
-            return Err(anyhow::anyhow!(
-                "error: instruction overflow\n{} | {}",
-                line_num,
-                line
-            ));
-        }
+    // Program blocks as (name, location counter), and the block in effect from each
+    // (instruction index, symbol index) on
+    let mut blocks = vec![(String::new(), addr)];
+    let mut block = 0;
+    let mut block_switches = vec![(0, 0, 0)];
+
+    // Symbols of the current control section start at `section_symbols`
+    let mut sections = Vec::new();
+    let mut section_symbols = 0;
+
+    let mut errors = Vec::new();
+    let mut overflowed = false;
+    let mut ended = false;
+
+    let mut statement = |line_num: Location, line: String, format| -> anyhow::Result<()> {
+        let defined = symtab.len();
+        let result = (|| {
+            let line = fold_line(&line_num, &line)?;
+            let Some((symbol, opcode, operand)) = line_statement(&line_num, &line, format)? else {
+                return Ok(());
+            };
 
-        match line.split_whitespace().collect::<Vec<_>>()[..] {
-            [symbol, opcode, operand] => {
-                instructions.push(InstructionType::SymbolOpcodeOperand(
-                    InstructionSymbolOpcodeOperand::new(line_num, addr, symbol, opcode, operand),
+            if ended {
+                return Err(anyhow::anyhow!(
+                    "error: statement after END\n{} | {}",
+                    line_num,
+                    line
                 ));
+            }
+            ended = opcode == "END";
 
-                symtab.push(SymbolMapping::new(symbol, addr));
-
-                match opcode {
-                    "BYTE" => {
-                        match operand.split("'").collect::<Vec<_>>()[..] {
-                            ["X", hex, ""] => addr += hex.len() >> 1,
-                            ["C", chars, ""] => addr += chars.len(),
-                            _ => {
-                                return Err(anyhow::anyhow!(
-                                    "error: invalid operand `{}`\n{} | {}",
-                                    operand,
-                                    line_num,
-                                    line
-                                ))
-                            }
-                        };
+            if addr > 0xfffff && !overflowed {
+                overflowed = true;
+                return Err(anyhow::anyhow!(
+                    "error: instruction overflow\n{} | {}",
+                    line_num,
+                    line
+                ));
+            }
+
+            // Literals still pending at CSECT or END are placed in front of it
+            if opcode == "CSECT" || opcode == "END" {
+                dump_literals(&line_num, &mut addr, &mut literals, &mut instructions);
+            }
+
+            // CSECT closes the current control section and starts a new one at 0
+            if opcode == "CSECT" {
+                blocks[block].1 = addr;
+                let end = assign_blocks(&blocks, &block_switches, &mut instructions, &mut symtab);
+                for sym in &mut symtab[section_symbols..] {
+                    sym.section = sections.len();
+                }
+                sections.push(ControlSection::new(&name, start, end - start));
+
+                name = symbol.unwrap_or_default().to_owned();
+                start = 0;
+                addr = 0;
+                saved_addr = None;
+                blocks = vec![(String::new(), addr)];
+                block = 0;
+                block_switches = vec![(instructions.len(), symtab.len(), 0)];
+                section_symbols = symtab.len();
+            }
+
+            // USE switches to the location counter of another block
+            if opcode == "USE" {
+                let name = operand.unwrap_or_default();
+
+                blocks[block].1 = addr;
+                block = match blocks.iter().position(|(block, _)| block == name) {
+                    Some(block) => block,
+                    None => {
+                        blocks.push((name.to_owned(), 0));
+                        blocks.len() - 1
                     }
-                    "RESB" => addr += usize::from_str_radix(&operand, 10)?,
-                    "RESW" => addr += 3 * usize::from_str_radix(&operand, 10)?,
-                    _ => addr += 3,
+                };
+                addr = blocks[block].1;
+
+                block_switches.push((instructions.len(), symtab.len(), block));
+            }
+
+            let mut ins = Statement::new(line_num.clone(), addr, symbol, opcode, operand);
+            let tokens = ins.tokens.clone();
+
+            // EQU takes its value from the operand instead of the location counter
+            let (loc, kind) = match (symbol, opcode, operand) {
+                (Some(_), "EQU", Some(operand)) => {
+                    let value = evaluate_operand(
+                        &line_num,
+                        &line,
+                        operand,
+                        &tokens,
+                        addr,
+                        &symtab[section_symbols..],
+                    )?;
+                    (value.value as usize, value.kind())
+                }
+                (_, "EQU", _) => {
+                    return Err(anyhow::anyhow!(
+                        "error: invalid instruction\n{} | {}",
+                        line_num,
+                        line
+                    ))
                 }
+                _ => (addr, SymbolKind::Relative),
+            };
+
+            // BYTE operands and literals keep their parsed constant
+            let literal = tokens.first().is_some_and(|token| token.text == "=");
+            let constant = match (opcode, operand) {
+                ("BYTE", Some(operand)) => Some(operand_constant(&tokens).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "error: invalid operand `{}`\n{} | {}",
+                        operand,
+                        line_num,
+                        line
+                    )
+                })?),
+                (_, Some(operand)) if literal => {
+                    Some(operand_constant(&tokens[1..]).ok_or_else(|| {
+                        anyhow::anyhow!(
+                            "error: invalid literal `{}`\n{} | {}",
+                            operand,
+                            line_num,
+                            line
+                        )
+                    })?)
+                }
+                _ => None,
+            };
+
+            ins.addr = loc;
+            ins.constant = constant.clone();
+            instructions.push(ins);
+
+            if let Some(symbol) = symbol {
+                symtab.push(SymbolMapping::with_kind(symbol, loc, kind));
             }
 
-            [opcode, operand] => {
-                instructions.push(InstructionType::OpcodeOperand(
-                    InstructionOpcodeOperand::new(line_num, addr, opcode, operand),
-                ));
+            if let ("EXTREF", Some(operand)) = (opcode, operand) {
+                let symbols = symbol_list(&tokens).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "error: invalid operand `{}`\n{} | {}",
+                        operand,
+                        line_num,
+                        line
+                    )
+                })?;
+                for symbol in symbols {
+                    symtab.push(SymbolMapping::with_kind(symbol, 0, SymbolKind::External));
+                }
+            }
 
-                addr += 3;
+            if let Some(constant) = constant.as_ref().filter(|_| literal) {
+                if !literals.contains(constant) {
+                    literals.push(constant.clone());
+                }
             }
 
-            [opcode] => {
-                instructions.push(InstructionType::OpcodeOnly(InstructionOpcodeOnly::new(
-                    line_num, addr, opcode,
-                )));
+            match (opcode, operand) {
+                ("BYTE", Some(_)) => addr += constant.as_ref().map_or(0, Constant::size),
+                ("RESB" | "RESW", Some(operand)) => {
+                    let value = evaluate_operand(
+                        &line_num,
+                        &line,
+                        operand,
+                        &tokens,
+                        addr,
+                        &symtab[section_symbols..],
+                    )?;
+                    let end = (value.value as usize)
+                        .checked_mul(if opcode == "RESW" { 3 } else { 1 })
+                        .and_then(|size| addr.checked_add(size));
 
-                addr += 3;
+                    match end {
+                        Some(end) if value.kind() == SymbolKind::Absolute => addr = end,
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "error: invalid operand `{}`\n{} | {}",
+                                operand,
+                                line_num,
+                                line
+                            ))
+                        }
+                    }
+                }
+                ("ORG", Some(operand)) => {
+                    let value = evaluate_operand(
+                        &line_num,
+                        &line,
+                        operand,
+                        &tokens,
+                        addr,
+                        &symtab[section_symbols..],
+                    )?;
+
+                    // Blocks other than the default one count from 0 until they are placed
+                    let lowest = if block == 0 { start } else { 0 };
+                    if value.value < lowest as isize {
+                        return Err(anyhow::anyhow!(
+                            "error: ORG below the start of the section\n{} | {}",
+                            line_num,
+                            line
+                        ));
+                    }
+
+                    saved_addr = Some(addr);
+                    addr = value.value as usize;
+                }
+                ("ORG", None) => match saved_addr.take() {
+                    Some(saved_addr) => addr = saved_addr,
+                    None => {
+                        return Err(anyhow::anyhow!(
+                            "error: ORG without a location to restore\n{} | {}",
+                            line_num,
+                            line
+                        ))
+                    }
+                },
+                ("LTORG", None) => {
+                    dump_literals(&line_num, &mut addr, &mut literals, &mut instructions)
+                }
+                _ => addr += instruction_size(opcode),
             }
 
-            _ => {
-                return Err(anyhow::anyhow!(
-                    "error: invalid instruction\n{} | {}",
-                    line_num,
-                    line
-                ))
+            Ok(())
+        })();
+
+        // The label of a bad statement before END is still defined at the current location,
+        // so its uses don't fail as well
+        if result.is_err() && symtab.len() == defined && !ended {
+            if let Some(label) = line_label(&line, format) {
+                symtab.push(SymbolMapping::new(label, addr));
             }
+        }
+
+        result
+    };
+
+    for (line_num, line, format) in source {
+        if let Err(error) = statement(line_num, line, format) {
+            errors.push(error);
+        }
+    }
+
+    if !ended {
+        errors.push(anyhow::anyhow!("error: missing END"));
+    }
+
+    blocks[block].1 = addr;
+    let end = assign_blocks(&blocks, &block_switches, &mut instructions, &mut symtab);
+    for sym in &mut symtab[section_symbols..] {
+        sym.section = sections.len();
+    }
+    sections.push(ControlSection::new(&name, start, end - start));
+
+    // END marks the end of the whole program rather than of its block
+    if let Some(ins) = instructions.last_mut().filter(|ins| ins.opcode == "END") {
+        ins.addr = end;
+    }
+
+    link_literals(&mut instructions);
+
+    (instructions, symtab, sections, errors)
+}
+
+/// START statement of the first line, giving the program name and start address.
+fn start_statement(
+    line_num: &Location,
+    line: &str,
+    format: SourceFormat,
+) -> anyhow::Result<Statement> {
+    let line = fold_line(line_num, line)?;
+    let Some((Some(symbol), "START", Some(operand))) = line_statement(line_num, &line, format)?
+    else {
+        return Err(anyhow::anyhow!("Missing START instruction"));
+    };
+    let addr = usize::from_str_radix(operand, 16)?;
+
+    Ok(Statement::new(
+        line_num.clone(),
+        addr,
+        Some(symbol),
+        "START",
+        Some(operand),
+    ))
+}
+
+/// Points each `=literal` operand at its entry in the first pool placed after it, so a
+/// literal used again after `LTORG` refers to the new pool rather than the old one.
+fn link_literals(instructions: &mut [Statement]) {
+    for i in 0..instructions.len() {
+        let (ins, rest) = instructions[i..].split_first_mut().unwrap();
+        if ins
+            .operand
+            .as_ref()
+            .is_some_and(|operand| operand.starts_with('='))
+        {
+            ins.literal = rest
+                .iter()
+                .filter(|entry| entry.label.as_deref() == Some("*"))
+                .find(|entry| entry.constant == ins.constant)
+                .map(|entry| entry.addr);
+        }
+    }
+}
+
+/// Places the program blocks of a control section one after another, in order of first
+/// appearance, turns block-relative locations into addresses, and returns the end address.
+fn assign_blocks(
+    blocks: &[(String, usize)],
+    block_switches: &[(usize, usize, usize)],
+    instructions: &mut [Statement],
+    symtab: &mut [SymbolMapping],
+) -> usize {
+    // The default block counts from the START address, the others from 0
+    let mut offsets = vec![0];
+    let mut end = blocks[0].1;
+    for (_, len) in &blocks[1..] {
+        offsets.push(end);
+        end += len;
+    }
+
+    for (i, &(ins_from, sym_from, block)) in block_switches.iter().enumerate() {
+        let (ins_to, sym_to) = match block_switches.get(i + 1) {
+            Some(&(ins_to, sym_to, _)) => (ins_to, sym_to),
+            None => (instructions.len(), symtab.len()),
         };
+
+        for sym in &mut symtab[sym_from..sym_to] {
+            if sym.kind == SymbolKind::Relative {
+                sym.addr += offsets[block];
+            }
+        }
+
+        for ins in &mut instructions[ins_from..ins_to] {
+            let absolute = ins.opcode == "EQU"
+                && symtab.iter().any(|sym| {
+                    Some(&sym.symbol) == ins.label.as_ref() && sym.kind == SymbolKind::Absolute
+                });
+
+            if !absolute {
+                ins.addr += offsets[block];
+            }
+        }
+    }
+
+    end
+}
+
+/// Source line with everything but character constants and comments in uppercase.
+fn fold_line(line_num: &Location, line: &str) -> anyhow::Result<String> {
+    fold_case(line).map_err(|error| anyhow::anyhow!("error: {}\n{} | {}", error, line_num, line))
+}
+
+/// Fields of a source line, leaving out its comment.
+fn line_fields<'a>(line_num: &Location, line: &'a str) -> anyhow::Result<Vec<&'a str>> {
+    tokenize(line)
+        .map(|tokens| split_fields(line, &tokens))
+        .map_err(|error| anyhow::anyhow!("error: {}\n{} | {}", error, line_num, line))
+}
+
+/// Label of a source line, found without lexing it, so a line that doesn't lex has one.
+fn line_label(line: &str, format: SourceFormat) -> Option<String> {
+    let label = match format {
+        SourceFormat::Free => {
+            let fields = field_spans(line)
+                .into_iter()
+                .map(|(start, end)| &line[start..end])
+                .collect::<Vec<_>>();
+            statement_fields(&fields, |_| None).0
+        }
+        SourceFormat::Fixed => Some(split_columns(line)[0]).filter(|label| !label.is_empty()),
+    };
+
+    label.map(str::to_ascii_uppercase)
+}
+
+/// Label, opcode and operand of a source line, or `None` for a blank or comment line. In
+/// fixed format a blank label column means there is no label.
+fn line_statement<'a>(
+    line_num: &Location,
+    line: &'a str,
+    format: SourceFormat,
+) -> anyhow::Result<Option<Fields<'a>>> {
+    match format {
+        SourceFormat::Free => {
+            let fields = line_fields(line_num, line)?;
+            Ok((!fields.is_empty()).then(|| statement_fields(&fields, |_| None)))
+        }
+        SourceFormat::Fixed => match split_columns(line) {
+            ["", "", ""] => Ok(None),
+            [_, "", _] => Err(anyhow::anyhow!(
+                "error: missing opcode\n{} | {}",
+                line_num,
+                line
+            )),
+            [label, opcode, operand] => Ok(Some((
+                Some(label).filter(|label| !label.is_empty()),
+                opcode,
+                Some(operand).filter(|operand| !operand.is_empty()),
+            ))),
+        },
+    }
+}
+
+/// Evaluates a non-negative operand in terms of the symbols defined so far in the control
+/// section. External references are left to pass 2.
+fn evaluate_operand(
+    line_num: &Location,
+    line: &str,
+    operand: &str,
+    tokens: &[Token],
+    addr: usize,
+    symtab: &[SymbolMapping],
+) -> anyhow::Result<Value> {
+    match evaluate(tokens, addr, symtab) {
+        Ok(value) if value.value >= 0 && value.externals.is_empty() => Ok(value),
+        Ok(_) => Err(anyhow::anyhow!(
+            "error: invalid operand `{}`\n{} | {}",
+            operand,
+            line_num,
+            line
+        )),
+        Err(error) => Err(anyhow::anyhow!("error: {}\n{} | {}", error, line_num, line)),
+    }
+}
+
+/// Places the pending literal pool at `addr`, one `*` line per literal.
+fn dump_literals(
+    line_num: &Location,
+    addr: &mut usize,
+    literals: &mut Vec<Constant>,
+    instructions: &mut Vec<Statement>,
+) {
+    for literal in literals.drain(..) {
+        let size = literal.size();
+        instructions.push(Statement {
+            constant: Some(literal.clone()),
+            ..Statement::new(
+                line_num.clone(),
+                *addr,
+                Some("*"),
+                format!("={}", literal),
+                None,
+            )
+        });
+
+        *addr += size;
+    }
+}
+
+/// The constant of a BYTE operand or literal, a single `C'...'` or `X'...'` token.
+fn operand_constant(tokens: &[Token]) -> Option<Constant> {
+    match tokens {
+        [token] => Constant::new(token),
+        _ => None,
+    }
+}
+
+/// Statement of a line in free format, without the comment after it, which is not lexed
+/// and may hold any text.
+fn free_statement(line: &str) -> &str {
+    let spans = field_spans(line);
+    let fields = spans
+        .iter()
+        .map(|&(start, end)| &line[start..end])
+        .collect::<Vec<_>>();
+
+    match statement_fields(&fields, |_| None) {
+        _ if fields.is_empty() => "",
+        (label, _, operand) => {
+            let used = label.is_some() as usize + 1 + operand.is_some() as usize;
+            &line[spans[0].0..spans[used - 1].1]
+        }
+    }
+}
+
+/// Splits the fields of a statement into label, opcode and operand, ignoring case. A first
+/// field that is not a mnemonic or directive is a label, as is one followed by a directive
+/// or by another mnemonic it can't take as operand. Whatever follows the operand, or the
+/// opcode of an instruction without one, is a comment. `names` tells which other names
+/// act as directives, and whether they take an operand.
+pub fn statement_fields<'a>(
+    fields: &[&'a str],
+    names: impl Fn(&str) -> Option<bool>,
+) -> Fields<'a> {
+    let directive = |field: &str| {
+        let field = field.to_ascii_uppercase();
+        DIRECTIVES.contains(&field.as_str()) || names(&field).is_some()
+    };
+    let known = |field: &str| directive(field) || is_mnemonic(&field.to_ascii_uppercase());
+    let takes_operand = |opcode: &str| {
+        let opcode = opcode.to_ascii_uppercase();
+        names(&opcode).unwrap_or_else(|| takes_operand(&opcode))
+    };
+
+    let (label, rest) = match fields {
+        [] => return (None, "", None),
+        [first, second, ..] if !known(first) && (known(second) || fields.len() > 2) => {
+            (Some(*first), &fields[1..])
+        }
+        [first, second, ..] if directive(second) || known(second) && !takes_operand(first) => {
+            (Some(*first), &fields[1..])
+        }
+        _ => (None, fields),
+    };
+
+    let opcode = rest[0];
+    let operand = rest.get(1).copied().filter(|_| takes_operand(opcode));
+
+    (label, opcode, operand)
+}
+
+/// Whether `name` is a machine instruction or an assembler directive.
+pub fn is_mnemonic(name: &str) -> bool {
+    DIRECTIVES.contains(&name) || OPCODE_MAP.contains_key(name.trim_start_matches('+'))
+}
+
+fn takes_operand(opcode: &str) -> bool {
+    match opcode {
+        "NOBASE" | "LTORG" | "CSECT" | "RSUB" => false,
+        _ => !matches!(OPCODE_MAP.get(opcode), Some((_, 1))),
+    }
+}
+
+fn instruction_size(opcode: &str) -> usize {
+    match opcode {
+        "BASE" | "NOBASE" | "LTORG" | "EQU" | "ORG" | "USE" | "CSECT" | "EXTDEF" | "EXTREF"
+        | "END" => 0,
+        _ if opcode.starts_with('+') => 4,
+        _ => match OPCODE_MAP.get(opcode) {
+            Some((_, format @ (1 | 2))) => *format,
+            _ => 3,
+        },
+    }
+}
+
+/// Source lines numbered from 1, all in `format`, as pass 1 takes them.
+#[cfg(test)]
+pub fn numbered<'a>(
+    source: &'a [&str],
+    format: SourceFormat,
+) -> impl Iterator<Item = (Location, String, SourceFormat)> + 'a {
+    source
+        .iter()
+        .enumerate()
+        .map(move |(i, line)| ((i + 1).into(), line.to_string(), format))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preprocessor::{expand_macros, ExpandedLine};
+
+    #[test]
+    fn test_statement_fields() {
+        assert_eq!(
+            statement_fields(&["FIRST", "STL", "RETADR", "SAVE", "RETURN"], |_| None),
+            (Some("FIRST"), "STL", Some("RETADR"))
+        );
+        assert_eq!(
+            statement_fields(&["EXIT", "RSUB"], |_| None),
+            (Some("EXIT"), "RSUB", None)
+        );
+        assert_eq!(
+            statement_fields(&["RSUB", "RETURN", "TO", "CALLER"], |_| None),
+            (None, "RSUB", None)
+        );
+        assert_eq!(
+            statement_fields(&["RDREC", "CSECT"], |_| None),
+            (Some("RDREC"), "CSECT", None)
+        );
+        assert_eq!(
+            statement_fields(&["+JSUB", "RDREC"], |_| None),
+            (None, "+JSUB", Some("RDREC"))
+        );
+        assert_eq!(statement_fields(&["FIX"], |_| None), (None, "FIX", None));
+        assert_eq!(
+            statement_fields(&["LOOP", "BADOP", "X"], |_| None),
+            (Some("LOOP"), "BADOP", Some("X"))
+        );
+        assert_eq!(
+            statement_fields(&["BADOP", "X"], |_| None),
+            (None, "BADOP", Some("X"))
+        );
+    }
+
+    #[test]
+    fn test_pass1_statements() {
+        let source = [
+            "COPY\tSTART\t1000",
+            "FIRST\tSTL\tRETADR\tSAVE CALLER'S ADDRESS",
+            "EXIT\tRSUB\treturn; see note",
+            "RETADR\tRESW\t1",
+            "\tEND\tFIRST",
+        ];
+
+        let (instructions, symtab, _, errors) = pass1(numbered(&source, SourceFormat::Free));
+        assert!(errors.is_empty());
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|ins| ins.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "1000\tCOPY\tSTART\t1000",
+                "1000\tFIRST\tSTL\tRETADR",
+                "1003\tEXIT\tRSUB\t",
+                "1006\tRETADR\tRESW\t1",
+                "1009\t\tEND\tFIRST",
+            ]
+        );
+        assert_eq!(
+            symtab.iter().map(|sym| sym.to_string()).collect::<Vec<_>>(),
+            vec!["FIRST\t1000", "EXIT\t1003", "RETADR\t1006"]
+        );
+    }
+
+    #[test]
+    fn test_pass1_literal_pools() {
+        let source = [
+            "COPY\tSTART\t0",
+            "\tLDA\t=C'EOF'",
+            "\tCOMP\t=C'EOF'",
+            "\tLTORG",
+            "\tLDA\t=C'EOF'",
+            "\tLDB\t=X'05'",
+            "\tEND\tCOPY",
+        ];
+
+        let (instructions, _, _, errors) = pass1(numbered(&source, SourceFormat::Free));
+        assert!(errors.is_empty());
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|ins| (ins.to_string(), ins.literal))
+                .collect::<Vec<_>>(),
+            vec![
+                ("0000\tCOPY\tSTART\t0".to_owned(), None),
+                ("0000\t\tLDA\t=C'EOF'".to_owned(), Some(0x0006)),
+                ("0003\t\tCOMP\t=C'EOF'".to_owned(), Some(0x0006)),
+                ("0006\t\tLTORG\t".to_owned(), None),
+                ("0006\t*\t=C'EOF'\t".to_owned(), None),
+                ("0009\t\tLDA\t=C'EOF'".to_owned(), Some(0x000F)),
+                ("000C\t\tLDB\t=X'05'".to_owned(), Some(0x0012)),
+                ("000F\t*\t=C'EOF'\t".to_owned(), None),
+                ("0012\t*\t=X'05'\t".to_owned(), None),
+                ("0013\t\tEND\tCOPY".to_owned(), None),
+            ]
+        );
+
+        for literal in ["=X'F'", "=X'0G'", "=C'EOF"] {
+            let source = [
+                "COPY\tSTART\t0",
+                &format!("\tLDA\t{}", literal),
+                "\tEND\tCOPY",
+            ];
+            let (_, _, _, errors) = pass1(numbered(&source, SourceFormat::Free));
+            assert_eq!(errors.len(), 1);
+        }
+    }
+
+    #[test]
+    fn test_pass1_org() {
+        let assemble = |source: &[&str]| pass1(numbered(source, SourceFormat::Free));
+
+        let (instructions, symtab, _, errors) = assemble(&[
+            "COPY\tSTART\t1000",
+            "\tLDA\t#1",
+            "\tORG\t*+X'100'",
+            "TAB\tBYTE\tX'F1'",
+            "\tORG",
+            "\tRSUB",
+            "\tEND\tCOPY",
+        ]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            instructions.iter().map(|ins| ins.addr).collect::<Vec<_>>(),
+            vec![0x1000, 0x1000, 0x1003, 0x1103, 0x1104, 0x1003, 0x1006]
+        );
+        assert_eq!(symtab[0].to_string(), "TAB\t1103");
+
+        let (_, _, _, errors) = assemble(&["COPY\tSTART\t0", "\tORG", "\tEND\tCOPY"]);
+        assert_eq!(errors.len(), 1);
+
+        let (_, _, sections, errors) =
+            assemble(&["COPY\tSTART\t1000", "\tORG\t10", "\tRSUB", "\tEND\tCOPY"]);
+        assert_eq!(
+            errors[0].to_string(),
+            "error: ORG below the start of the section\n2 | ORG\t10"
+        );
+        assert_eq!(sections[0].to_string(), "HCOPY  001000000003");
+    }
+
+    #[test]
+    fn test_pass1_blocks() {
+        let source = [
+            "COPY\tSTART\t0",
+            "FIRST\tSTL\tRETADR",
+            "\tUSE\tCDATA",
+            "RETADR\tRESW\t1",
+            "LENGTH\tRESW\t1",
+            "\tUSE\tCBLKS",
+            "BUFFER\tRESB\t4096",
+            "BUFEND\tEQU\t*",
+            "MAXLEN\tEQU\tBUFEND-BUFFER",
+            "\tUSE",
+            "\tLDA\tLENGTH",
+            "\tUSE\tCDATA",
+            "EOF\tBYTE\tC'EOF'",
+            "\tEND\tFIRST",
+        ];
+
+        let (instructions, symtab, sections, errors) = pass1(numbered(&source, SourceFormat::Free));
+        assert!(errors.is_empty());
+
+        // Default block at 0000, CDATA at 0006 and CBLKS at 000F
+        assert_eq!(
+            symtab
+                .iter()
+                .map(|sym| (sym.to_string(), sym.kind))
+                .collect::<Vec<_>>(),
+            vec![
+                ("FIRST\t0000".to_owned(), SymbolKind::Relative),
+                ("RETADR\t0006".to_owned(), SymbolKind::Relative),
+                ("LENGTH\t0009".to_owned(), SymbolKind::Relative),
+                ("BUFFER\t000F".to_owned(), SymbolKind::Relative),
+                ("BUFEND\t100F".to_owned(), SymbolKind::Relative),
+                ("MAXLEN\t1000".to_owned(), SymbolKind::Absolute),
+                ("EOF\t000C".to_owned(), SymbolKind::Relative),
+            ]
+        );
+        assert_eq!(
+            instructions
+                .iter()
+                .filter(|ins| ins.opcode == "EQU" || ins.opcode == "LDA")
+                .map(|ins| ins.addr)
+                .collect::<Vec<_>>(),
+            vec![0x100F, 0x1000, 0x0003]
+        );
+        assert_eq!(sections[0].to_string(), "HCOPY  00000000100F");
     }
 
-    Ok((instructions, symtab))
+    #[test]
+    fn test_pass1_fixed_columns() {
+        let source = [
+            "COPY     START  1000",
+            ". it's a comment line, with punctuation!",
+            "FIX      LDA    ZERO               LABEL NAMED LIKE A MNEMONIC",
+            "         RSUB                      RETURN TO CALLER",
+            "ZERO     WORD   0",
+            "         END    FIX",
+        ];
+
+        let (instructions, symtab, _, errors) = pass1(numbered(&source, SourceFormat::Fixed));
+        assert!(errors.is_empty());
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|ins| ins.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "1000\tCOPY\tSTART\t1000",
+                "1000\tFIX\tLDA\tZERO",
+                "1003\t\tRSUB\t",
+                "1006\tZERO\tWORD\t0",
+                "1009\t\tEND\tFIX",
+            ]
+        );
+        assert_eq!(
+            symtab.iter().map(|sym| sym.to_string()).collect::<Vec<_>>(),
+            vec!["FIX\t1000", "ZERO\t1006"]
+        );
+    }
+
+    #[test]
+    fn test_pass1_fixed_columns_macros() {
+        let source = [
+            "COPY     START  1000",
+            "WAIT     MACRO  &DEV               WAIT FOR A DEVICE",
+            "$LOOP    TD     =X'&DEV'",
+            "         JEQ    $LOOP",
+            "         MEND",
+            "FIRST    WAIT   F1                 INPUT DEVICE",
+            "         WAIT   05",
+            "         RSUB",
+            "         END    FIRST",
+        ];
+
+        let source = ExpandedLine::numbered(&source.map(fixed_statement));
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert!(errors.is_empty());
+
+        let (instructions, symtab, _, errors) = pass1(expanded.iter().map(|line| {
+            (
+                line.location.clone(),
+                line.to_string(),
+                line.format(SourceFormat::Fixed),
+            )
+        }));
+        assert!(errors.is_empty());
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|ins| ins.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "1000\tCOPY\tSTART\t1000",
+                "1000\tFIRST\tEQU\t*",
+                "1000\t$AALOOP\tTD\t=X'F1'",
+                "1003\t\tJEQ\t$AALOOP",
+                "1006\t$ABLOOP\tTD\t=X'05'",
+                "1009\t\tJEQ\t$ABLOOP",
+                "100C\t\tRSUB\t",
+                "100F\t*\t=X'F1'\t",
+                "1010\t*\t=X'05'\t",
+                "1011\t\tEND\tFIRST",
+            ]
+        );
+        assert_eq!(symtab[0].to_string(), "FIRST\t1000");
+    }
+
+    #[test]
+    fn test_pass1_collects_errors() {
+        let source = [
+            "COPY\tSTART\t0",
+            "\tLDA\t=X'F'F",
+            "EOF\tBYTE\tC'EOF",
+            "LEN\tEQU",
+            "\tRSUB",
+            "\tEND\tCOPY",
+            "SUB\tCSECT",
+            "\tRSUB",
+        ];
+
+        let (instructions, symtab, _, errors) = pass1(numbered(&source, SourceFormat::Free));
+        assert_eq!(
+            instructions
+                .iter()
+                .map(|ins| ins.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "0000\tCOPY\tSTART\t0",
+                "0000\t\tRSUB\t",
+                "0003\t\tEND\tCOPY"
+            ]
+        );
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "error: invalid literal `=X'F'F`\n2 | LDA\t=X'F'F",
+                "error: unterminated constant `C'EOF`\n3 | EOF\tBYTE\tC'EOF",
+                "error: invalid instruction\n4 | LEN\tEQU",
+                "error: statement after END\n7 | SUB\tCSECT",
+                "error: statement after END\n8 | RSUB",
+            ]
+        );
+        assert_eq!(
+            symtab.iter().map(|sym| sym.to_string()).collect::<Vec<_>>(),
+            vec!["EOF\t0000", "LEN\t0000"]
+        );
+
+        let (_, _, _, errors) = pass1(numbered(&source[..5], SourceFormat::Free));
+        assert_eq!(errors.last().unwrap().to_string(), "error: missing END");
+    }
 }
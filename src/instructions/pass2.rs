@@ -1,125 +1,527 @@
-use super::InstructionType;
+use super::evaluate;
+use super::split_operands;
+use super::symbol_list;
+use super::Location;
+use super::Modification;
+use super::Statement;
+use super::SymbolKind;
 use super::SymbolMapping;
+use super::Token;
+use super::Value;
 use super::OPCODE_MAP;
+use super::REGISTER_MAP;
 
+const FLAG_N: usize = 0x20000;
+const FLAG_I: usize = 0x10000;
+const FLAG_X: usize = 0x8000;
+const FLAG_B: usize = 0x4000;
+const FLAG_P: usize = 0x2000;
+const FLAG_E: usize = 0x1000;
+
+/// Encodes the statements of pass 1, leaving the object code of a bad one empty.
 pub fn pass2(
-    instructions: &Vec<InstructionType>,
-    symtab: &Vec<SymbolMapping>,
-) -> anyhow::Result<Vec<String>> {
+    instructions: &[Statement],
+    symtab: &[SymbolMapping],
+) -> (Vec<String>, Vec<Vec<Modification>>, Vec<anyhow::Error>) {
     let mut objcodes = Vec::new();
+    let mut modifications = vec![Vec::new()];
+    let mut base = None;
+    let mut section_symtab = section_symbols(symtab, 0);
 
-    for ins_type in instructions {
-        let objcode = match ins_type {
-            InstructionType::SymbolOpcodeOperand(ins) => match ins.opcode.as_str() {
-                "START" => "".to_owned(),
-                "BYTE" => match ins.operand.split("'").collect::<Vec<_>>()[..] {
-                    ["X", hex, ""] => hex.to_owned(),
-                    ["C", chars, ""] => chars.chars().map(|c| format!("{:02X}", c as u8)).collect(),
-                    _ => {
-                        return Err(anyhow::anyhow!(
-                            "error: invalid operand `{}`\n{} | {}",
-                            ins.operand,
-                            ins.line,
-                            ins.opcode
-                        ))
-                    }
-                },
-                "WORD" => {
-                    format!("{:06X}", usize::from_str_radix(&ins.operand, 10)?)
-                }
-                "RESB" => "".to_owned(),
-                "RESW" => "".to_owned(),
-                _ => {
-                    if let Some(opcode) = OPCODE_MAP.get(ins.opcode.as_str()) {
-                        let mut is_x = false;
-
-                        let operand = match ins.operand.split(",").collect::<Vec<_>>()[..] {
-                            [operand, "X"] => {
-                                is_x = true;
-                                operand
-                            }
-                            [operand] => operand,
-                            _ => unreachable!(),
-                        };
-
-                        if let Some(sym) = symtab.iter().find(|sym| sym.symbol == operand) {
-                            format!(
-                                "{:02X}{:04X}",
-                                opcode,
-                                sym.addr + if is_x { 0x8000 } else { 0 }
-                            )
-                        } else {
-                            return Err(anyhow::anyhow!(
-                                "error: invalid operand `{}`\n{} | {}",
-                                ins.operand,
-                                ins.line,
-                                ins.opcode
-                            ));
-                        }
-                    } else {
-                        return Err(anyhow::anyhow!(
-                            "error: invalid opcode\n{} | {}",
-                            ins.line,
-                            ins.opcode
-                        ));
-                    }
-                }
-            },
+    let mut errors = Vec::new();
 
-            InstructionType::OpcodeOperand(ins) => match ins.opcode.as_str() {
-                "END" => "".to_owned(),
-                _ => {
-                    if let Some(opcode) = OPCODE_MAP.get(ins.opcode.as_str()) {
-                        let mut is_x = false;
-
-                        let operand = match ins.operand.split(",").collect::<Vec<_>>()[..] {
-                            [operand, "X"] => {
-                                is_x = true;
-                                operand
-                            }
-                            [operand] => operand,
-                            _ => unreachable!(),
-                        };
-
-                        if let Some(sym) = symtab.iter().find(|sym| sym.symbol == operand) {
-                            format!(
-                                "{:02X}{:04X}",
-                                opcode,
-                                sym.addr + if is_x { 0x8000 } else { 0 }
-                            )
-                        } else {
-                            return Err(anyhow::anyhow!(
-                                "error: invalid operand `{}`\n{} | {}",
-                                ins.operand,
-                                ins.line,
-                                ins.opcode
-                            ));
-                        }
-                    } else {
-                        return Err(anyhow::anyhow!(
-                            "error: invalid opcode\n{} | {}",
-                            ins.line,
-                            ins.opcode
-                        ));
-                    }
-                }
-            },
+    for ins in instructions {
+        if ins.opcode == "CSECT" {
+            modifications.push(Vec::new());
+            base = None;
+            section_symtab = section_symbols(symtab, modifications.len() - 1);
+        }
+
+        let section_modifications = modifications.last_mut().unwrap();
+        match encode_statement(ins, &mut base, section_symtab, section_modifications) {
+            Ok(objcode) => objcodes.push(objcode),
+            Err(error) => {
+                errors.push(error);
+                objcodes.push("".to_owned());
+            }
+        }
+    }
+
+    (objcodes, modifications, errors)
+}
 
-            InstructionType::OpcodeOnly(ins) => {
-                if let Some(opcode) = OPCODE_MAP.get(ins.opcode.as_str()) {
-                    format!("{:02X}0000", opcode)
-                } else {
+/// Object code of a statement, keeping track of `BASE` and adding the modification records
+/// it needs.
+fn encode_statement(
+    ins: &Statement,
+    base: &mut Option<usize>,
+    section_symtab: &[SymbolMapping],
+    section_modifications: &mut Vec<Modification>,
+) -> anyhow::Result<String> {
+    let (line, addr, opcode, operand) = (
+        &ins.line,
+        ins.addr,
+        ins.opcode.as_str(),
+        ins.operand.as_deref(),
+    );
+
+    let objcode = match opcode {
+        "START" | "END" | "RESB" | "RESW" | "LTORG" | "EQU" | "ORG" | "USE" | "EXTREF"
+        | "CSECT" => "".to_owned(),
+        "EXTDEF" => {
+            let symbols = symbol_list(&ins.tokens).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "error: invalid operand `{}`\n{} | {}",
+                    operand.unwrap_or_default(),
+                    line,
+                    opcode
+                )
+            })?;
+            for symbol in symbols {
+                if !section_symtab
+                    .iter()
+                    .any(|sym| sym.symbol == symbol && sym.kind != SymbolKind::External)
+                {
                     return Err(anyhow::anyhow!(
-                        "error: invalid opcode\n{} | {}",
-                        ins.line,
-                        ins.opcode
+                        "error: undefined symbol `{}`\n{} | {}",
+                        symbol,
+                        line,
+                        opcode
                     ));
                 }
             }
+            "".to_owned()
+        }
+        "BYTE" => encode_constant(ins)?,
+        "WORD" => {
+            let value = resolve_value(line, addr, opcode, &ins.tokens, section_symtab)?;
+            section_modifications.extend(relocations(addr, 6, &value));
+            format!("{:06X}", value.value & 0xFFFFFF)
+        }
+        "BASE" => {
+            let value = resolve_value(line, addr, opcode, &ins.tokens, section_symtab)?;
+            *base = usize::try_from(value.value).ok();
+            "".to_owned()
+        }
+        "NOBASE" => {
+            *base = None;
+            "".to_owned()
+        }
+        _ if opcode.starts_with('=') => encode_constant(ins)?,
+        _ => {
+            let (objcode, relocations) = encode(ins, *base, section_symtab)?;
+            section_modifications.extend(relocations);
+            objcode
+        }
+    };
+
+    Ok(objcode)
+}
+
+/// Symbols of a control section, which pass 1 keeps next to each other.
+fn section_symbols(symtab: &[SymbolMapping], section: usize) -> &[SymbolMapping] {
+    let start = symtab
+        .iter()
+        .position(|sym| sym.section == section)
+        .unwrap_or(symtab.len());
+    let len = symtab[start..]
+        .iter()
+        .take_while(|sym| sym.section == section)
+        .count();
+
+    &symtab[start..start + len]
+}
+
+/// Modification records for a field at `addr` of `len` half-bytes holding `value`.
+fn relocations(addr: usize, len: usize, value: &Value) -> Vec<Modification> {
+    let mut modifications = Vec::new();
+
+    if value.relative == 1 {
+        modifications.push(Modification::new(addr, len));
+    }
+    for external in &value.externals {
+        modifications.push(Modification::with_symbol(addr, len, external));
+    }
+
+    modifications
+}
+
+/// Object code of the constant pass 1 parsed for a BYTE operand or literal pool entry.
+fn encode_constant(ins: &Statement) -> anyhow::Result<String> {
+    match &ins.constant {
+        Some(constant) => Ok(constant.objcode()),
+        None => Err(anyhow::anyhow!(
+            "error: invalid operand `{}`\n{} | {}",
+            ins.operand.as_deref().unwrap_or(&ins.opcode),
+            ins.line,
+            ins.opcode
+        )),
+    }
+}
+
+/// Encodes an instruction along with the modification records its address field needs.
+fn encode(
+    ins: &Statement,
+    base: Option<usize>,
+    symtab: &[SymbolMapping],
+) -> anyhow::Result<(String, Vec<Modification>)> {
+    let (line, mnemonic) = (&ins.line, ins.opcode.as_str());
+
+    if let Some(mnemonic) = mnemonic.strip_prefix('+') {
+        return match lookup_opcode(line, mnemonic)? {
+            (_, 3) => format4(ins, mnemonic, symtab),
+            _ => Err(anyhow::anyhow!(
+                "error: invalid opcode\n{} | +{}",
+                line,
+                mnemonic
+            )),
         };
+    }
+
+    let objcode = match lookup_opcode(line, mnemonic)? {
+        (opcode, 1) => format1(ins, opcode)?,
+        (opcode, 2) => format2(ins, opcode)?,
+        _ => format3(ins, base, symtab)?,
+    };
+
+    Ok((objcode, Vec::new()))
+}
 
-        objcodes.push(objcode);
+/// Encodes a 1 byte `op` instruction.
+fn format1(ins: &Statement, opcode: usize) -> anyhow::Result<String> {
+    match &ins.operand {
+        None => Ok(format!("{:02X}", opcode)),
+        Some(operand) => Err(anyhow::anyhow!(
+            "error: invalid operand `{}`\n{} | {}",
+            operand,
+            ins.line,
+            ins.opcode
+        )),
     }
+}
+
+/// Encodes a 2 byte `op r1 r2` instruction.
+fn format2(ins: &Statement, opcode: usize) -> anyhow::Result<String> {
+    let invalid_operand = || {
+        anyhow::anyhow!(
+            "error: invalid operand `{}`\n{} | {}",
+            ins.operand.as_deref().unwrap_or_default(),
+            ins.line,
+            ins.opcode
+        )
+    };
+
+    let register = |r: &[Token]| match r {
+        [r] => REGISTER_MAP
+            .get(r.text.as_str())
+            .copied()
+            .ok_or_else(invalid_operand),
+        _ => Err(invalid_operand()),
+    };
+    let number = |n: &[Token], range: std::ops::RangeInclusive<usize>| match n {
+        [n] => n
+            .text
+            .parse()
+            .ok()
+            .filter(|n| range.contains(n))
+            .ok_or_else(invalid_operand),
+        _ => Err(invalid_operand()),
+    };
+
+    let (r1, r2) = match (ins.opcode.as_str(), &split_operands(&ins.tokens)[..]) {
+        ("SVC", [n]) => (number(n, 0..=15)?, 0),
+        ("SHIFTL" | "SHIFTR", [r1, n]) => (register(r1)?, number(n, 1..=16)? - 1),
+        ("CLEAR" | "TIXR", [r1]) => (register(r1)?, 0),
+        ("ADDR" | "COMPR" | "DIVR" | "MULR" | "RMO" | "SUBR", [r1, r2]) => {
+            (register(r1)?, register(r2)?)
+        }
+        _ => return Err(invalid_operand()),
+    };
+
+    Ok(format!("{:02X}{:X}{:X}", opcode, r1, r2))
+}
+
+/// Resolved operand of a format 3/4 instruction.
+struct Target {
+    addr: usize,
+    flags: usize,
+    value: Value,
+}
+
+/// Encodes a 3 byte `op n i x b p e disp` instruction, using PC-relative displacement
+/// and falling back to base-relative displacement when `BASE` is in effect.
+fn format3(
+    ins: &Statement,
+    base: Option<usize>,
+    symtab: &[SymbolMapping],
+) -> anyhow::Result<String> {
+    let (line, addr, mnemonic) = (&ins.line, ins.addr, ins.opcode.as_str());
+    let opcode = lookup_opcode(line, mnemonic)?.0 << 16;
+
+    let operand = match &ins.operand {
+        Some(operand) => operand,
+        None => return Ok(format!("{:06X}", opcode | FLAG_N | FLAG_I)),
+    };
 
-    Ok(objcodes)
+    let target = resolve_operand(ins, mnemonic, symtab)?;
+
+    match target.value.kind() {
+        SymbolKind::Absolute if target.addr <= 0xFFF => {
+            return Ok(format!("{:06X}", opcode | target.flags | target.addr));
+        }
+        SymbolKind::Absolute => {}
+        SymbolKind::Relative => {
+            let disp = target.addr as isize - (addr as isize + 3);
+            if (-2048..=2047).contains(&disp) {
+                return Ok(format!(
+                    "{:06X}",
+                    opcode | target.flags | FLAG_P | (disp as usize & 0xFFF)
+                ));
+            }
+
+            if let Some(disp) = base.and_then(|base| target.addr.checked_sub(base)) {
+                if disp <= 0xFFF {
+                    return Ok(format!("{:06X}", opcode | target.flags | FLAG_B | disp));
+                }
+            }
+        }
+        SymbolKind::External => {
+            return Err(anyhow::anyhow!(
+                "error: external reference needs format 4 `{}`\n{} | {}",
+                operand,
+                line,
+                mnemonic
+            ))
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "error: displacement out of range `{}`\n{} | {}",
+        operand,
+        line,
+        mnemonic
+    ))
+}
+
+/// Encodes a 4 byte `op n i x b p e address` instruction with a 20 bit address.
+fn format4(
+    ins: &Statement,
+    mnemonic: &str,
+    symtab: &[SymbolMapping],
+) -> anyhow::Result<(String, Vec<Modification>)> {
+    let (line, addr, operand) = (&ins.line, ins.addr, ins.operand.as_deref());
+    let opcode = lookup_opcode(line, mnemonic)?.0 << 24;
+
+    let (target, flags, relocations) = match operand {
+        Some(_) => {
+            let target = resolve_operand(ins, mnemonic, symtab)?;
+            (
+                target.addr,
+                target.flags,
+                relocations(addr + 1, 5, &target.value),
+            )
+        }
+        None => (0, FLAG_N | FLAG_I, Vec::new()),
+    };
+
+    if target > 0xFFFFF {
+        return Err(anyhow::anyhow!(
+            "error: address out of range `{}`\n{} | {}",
+            operand.unwrap_or_default(),
+            line,
+            mnemonic
+        ));
+    }
+
+    Ok((
+        format!("{:08X}", opcode | ((flags | FLAG_E) << 8) | target),
+        relocations,
+    ))
+}
+
+fn lookup_opcode(line: &Location, mnemonic: &str) -> anyhow::Result<(usize, usize)> {
+    if let Some(opcode) = OPCODE_MAP.get(mnemonic) {
+        Ok(*opcode)
+    } else {
+        Err(anyhow::anyhow!(
+            "error: invalid opcode\n{} | {}",
+            line,
+            mnemonic
+        ))
+    }
+}
+
+/// Evaluates the tokens of an expression operand at `addr`.
+fn resolve_value(
+    line: &Location,
+    addr: usize,
+    mnemonic: &str,
+    tokens: &[Token],
+    symtab: &[SymbolMapping],
+) -> anyhow::Result<Value> {
+    evaluate(tokens, addr, symtab)
+        .map_err(|error| anyhow::anyhow!("error: {}\n{} | {}", error, line, mnemonic))
+}
+
+/// Resolves `expr`, `expr,X`, `#expr`, `@expr` or `=literal`, a literal being at the pool
+/// entry `literal` pass 1 linked it to.
+fn resolve_operand(
+    ins: &Statement,
+    mnemonic: &str,
+    symtab: &[SymbolMapping],
+) -> anyhow::Result<Target> {
+    let (line, addr) = (&ins.line, ins.addr);
+    let invalid_operand = || {
+        anyhow::anyhow!(
+            "error: invalid operand `{}`\n{} | {}",
+            ins.operand.as_deref().unwrap_or_default(),
+            line,
+            mnemonic
+        )
+    };
+
+    let (target, flags) = match ins.tokens.split_first() {
+        Some((prefix, _)) if prefix.text == "=" => {
+            return match ins.literal {
+                Some(literal) => Ok(Target {
+                    addr: literal,
+                    flags: FLAG_N | FLAG_I,
+                    value: Value::new(literal as isize, 1),
+                }),
+                None => Err(invalid_operand()),
+            };
+        }
+        Some((prefix, target)) if prefix.text == "#" => (target, FLAG_I),
+        Some((prefix, target)) if prefix.text == "@" => (target, FLAG_N),
+        _ => match split_operands(&ins.tokens)[..] {
+            [target, [ref index]] if index.text == "X" => (target, FLAG_N | FLAG_I | FLAG_X),
+            [target] => (target, FLAG_N | FLAG_I),
+            _ => return Err(invalid_operand()),
+        },
+    };
+
+    let value = resolve_value(line, addr, mnemonic, target, symtab)?;
+    if value.value < 0 {
+        return Err(invalid_operand());
+    }
+
+    Ok(Target {
+        addr: value.value as usize,
+        flags,
+        value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::Constant;
+
+    #[test]
+    fn test_pass2_addressing_modes() {
+        let instructions = vec![
+            Statement::new(1, 0x0000, Some("FIRST"), "STL", Some("RETADR")),
+            Statement::new(2, 0x0003, None, "RSUB", None),
+            Statement::new(3, 0x0006, Some("LOOP"), "STCH", Some("BUFFER,X")),
+            Statement::new(4, 0x0009, None, "+JSUB", Some("RDREC")),
+            Statement::new(5, 0x000D, None, "LDA", Some("#3")),
+            Statement::new(6, 0x0010, None, "+LDT", Some("#4096")),
+            Statement::new(7, 0x0014, None, "J", Some("@RETADR")),
+            Statement::new(8, 0x0017, None, "BASE", Some("BUFFER")),
+            Statement::new(9, 0x0017, None, "STX", Some("BUFEND")),
+            Statement::new(10, 0x001A, None, "NOBASE", None),
+        ];
+        let symtab = vec![
+            SymbolMapping::new("RETADR", 0x0030),
+            SymbolMapping::new("BUFFER", 0x0036),
+            SymbolMapping::new("BUFEND", 0x1000),
+            SymbolMapping::new("RDREC", 0x1036),
+        ];
+
+        let (objcodes, modifications, errors) = pass2(&instructions, &symtab);
+        assert!(errors.is_empty());
+        assert_eq!(
+            objcodes,
+            vec![
+                "17202D", "4F0000", "57A02D", "4B101036", "010003", "75101000", "3E2019", "",
+                "134FCA", ""
+            ]
+        );
+        assert_eq!(
+            modifications[0]
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>(),
+            vec!["M00000A05"]
+        );
+    }
+
+    #[test]
+    fn test_pass2_format1_format2() {
+        let instructions = vec![
+            Statement::new(1, 0x0000, None, "FIX", None),
+            Statement::new(2, 0x0001, None, "CLEAR", Some("X")),
+            Statement::new(3, 0x0003, None, "COMPR", Some("A,S")),
+            Statement::new(4, 0x0005, None, "SHIFTL", Some("T,4")),
+            Statement::new(5, 0x0007, None, "SVC", Some("10")),
+        ];
+
+        let (objcodes, _, errors) = pass2(&instructions, &[]);
+        assert!(errors.is_empty());
+        assert_eq!(objcodes, vec!["C4", "B410", "A004", "A453", "B0A0"]);
+    }
+
+    #[test]
+    fn test_pass2_literals() {
+        let literal = |line, addr, operand, literal| Statement {
+            literal: Some(literal),
+            ..Statement::new(line, addr, None, "LDA", Some(operand))
+        };
+        let entry = |line, addr, constant: Constant| Statement {
+            constant: Some(constant.clone()),
+            ..Statement::new(line, addr, Some("*"), format!("={}", constant), None)
+        };
+        let eof = Constant::Chars("EOF".to_owned());
+        let instructions = vec![
+            literal(1, 0x0000, "=C'EOF'", 0x0003),
+            entry(2, 0x0003, eof.clone()),
+            literal(3, 0x0006, "=C'EOF'", 0x000C),
+            literal(4, 0x0009, "=X'05'", 0x000F),
+            entry(5, 0x000C, eof),
+            entry(5, 0x000F, Constant::Hex("05".to_owned())),
+            Statement {
+                constant: Some(Constant::Hex("F1".to_owned())),
+                ..Statement::new(6, 0x0010, Some("EOF"), "BYTE", Some("X'F1'"))
+            },
+        ];
+
+        let (objcodes, _, errors) = pass2(&instructions, &[]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            objcodes,
+            vec!["032000", "454F46", "032003", "032003", "454F46", "05", "F1"]
+        );
+    }
+
+    #[test]
+    fn test_pass2_collects_errors() {
+        let instructions = vec![
+            Statement::new(1, 0x0000, None, "LDZ", Some("BUFFER")),
+            Statement::new(2, 0x0003, None, "STA", Some("BUF")),
+            Statement::new(3, 0x0006, None, "CLEAR", Some("Q")),
+            Statement::new(4, 0x0008, None, "RSUB", None),
+        ];
+        let symtab = vec![SymbolMapping::new("BUFFER", 0x0030)];
+
+        let (objcodes, _, errors) = pass2(&instructions, &symtab);
+        assert_eq!(objcodes, vec!["", "", "", "4F0000"]);
+        assert_eq!(
+            errors.iter().map(|e| e.to_string()).collect::<Vec<_>>(),
+            vec![
+                "error: invalid opcode\n1 | LDZ",
+                "error: undefined symbol `BUF`\n2 | STA",
+                "error: invalid operand `Q`\n3 | CLEAR",
+            ]
+        );
+    }
 }
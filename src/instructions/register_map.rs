@@ -0,0 +1,17 @@
+use std::collections::HashMap;
+
+lazy_static::lazy_static! {
+    pub static ref REGISTER_MAP: HashMap<&'static str, usize> = {
+        HashMap::from([
+            ("A", 0),
+            ("X", 1),
+            ("L", 2),
+            ("B", 3),
+            ("S", 4),
+            ("T", 5),
+            ("F", 6),
+            ("PC", 8),
+            ("SW", 9),
+        ])
+    };
+}
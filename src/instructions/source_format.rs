@@ -0,0 +1,10 @@
+/// Layout of the fields in a source line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceFormat {
+    /// Fields separated by whitespace, with labels told apart by the opcode that follows.
+    #[default]
+    Free,
+    /// Beck's fixed columns: label in 1-8, opcode in 10-15, operand in 17-35 and comments
+    /// after that.
+    Fixed,
+}
@@ -0,0 +1,78 @@
+use std::fmt;
+
+use super::tokenize;
+use super::Constant;
+use super::Location;
+use super::Token;
+
+/// A source statement with its address, `[label] opcode [operand]`.
+#[derive(Debug)]
+pub struct Statement {
+    pub line: Location,
+    pub addr: usize,
+    pub label: Option<String>,
+    pub opcode: String,
+    pub operand: Option<String>,
+    /// Tokens of the operand, which the passes work from.
+    pub tokens: Vec<Token>,
+    /// Address of the pool entry a `=literal` operand refers to.
+    pub literal: Option<usize>,
+    /// Parsed constant of a BYTE operand, a literal or a literal pool entry.
+    pub constant: Option<Constant>,
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04X}\t{}\t{}\t{}",
+            self.addr,
+            self.label.as_deref().unwrap_or_default(),
+            self.opcode,
+            self.operand.as_deref().unwrap_or_default()
+        )
+    }
+}
+
+impl Statement {
+    /// Lexes the operand into the statement's tokens. Pass 1 has lexed the whole line by
+    /// then, so an operand that doesn't lex is not expected and gets no tokens.
+    pub fn new(
+        line: impl Into<Location>,
+        addr: usize,
+        label: Option<&str>,
+        opcode: impl Into<String>,
+        operand: Option<&str>,
+    ) -> Self {
+        Self {
+            line: line.into(),
+            addr,
+            label: label.map(str::to_owned),
+            opcode: opcode.into(),
+            operand: operand.map(str::to_owned),
+            tokens: operand
+                .and_then(|operand| tokenize(operand).ok())
+                .unwrap_or_default(),
+            literal: None,
+            constant: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statement() {
+        let statement = Statement::new(1, 0, Some("LOOP"), "JSUB", Some("RDREC"));
+        assert_eq!(format!("{}", statement), "0000\tLOOP\tJSUB\tRDREC");
+
+        let statement = Statement::new(1, 0, None, "LDA", Some("#4096"));
+        assert_eq!(format!("{}", statement), "0000\t\tLDA\t#4096");
+        assert_eq!(statement.tokens.len(), 2);
+
+        let statement = Statement::new(1, 0, None, "RSUB", None);
+        assert_eq!(format!("{}", statement), "0000\t\tRSUB\t");
+    }
+}
@@ -1,8 +1,17 @@
 use std::fmt;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Absolute,
+    Relative,
+    External,
+}
+
 pub struct SymbolMapping {
     pub symbol: String,
     pub addr: usize,
+    pub kind: SymbolKind,
+    pub section: usize,
 }
 
 impl fmt::Display for SymbolMapping {
@@ -13,9 +22,15 @@ impl fmt::Display for SymbolMapping {
 
 impl SymbolMapping {
     pub fn new(symbol: impl Into<String>, addr: usize) -> Self {
+        Self::with_kind(symbol, addr, SymbolKind::Relative)
+    }
+
+    pub fn with_kind(symbol: impl Into<String>, addr: usize, kind: SymbolKind) -> Self {
         Self {
             symbol: symbol.into(),
             addr,
+            kind,
+            section: 0,
         }
     }
 }
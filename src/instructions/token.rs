@@ -0,0 +1,51 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// Label, mnemonic, directive or register name.
+    Symbol,
+    Number,
+    /// `C'...'` or `X'...'`, quotes included.
+    Constant,
+    Comma,
+    /// One of `+ - * / ( ) # @ =`.
+    Operator,
+    /// A `.` comment up to the end of the line.
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Byte range of `text` in its line.
+    pub span: (usize, usize),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Token {
+    pub fn new(kind: TokenKind, text: &str, start: usize) -> Self {
+        Self {
+            kind,
+            text: text.to_owned(),
+            span: (start, start + text.len()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token() {
+        let token = Token::new(TokenKind::Constant, "C'EOF'", 4);
+        assert_eq!(format!("{}", token), "C'EOF'");
+        assert_eq!(token.span, (4, 10));
+    }
+}
@@ -0,0 +1,331 @@
+use std::{iter::Peekable, str::CharIndices};
+
+use super::Token;
+use super::TokenKind;
+
+/// Splits a source line into tokens. Character constants keep their spaces and commas, and
+/// a `.` that starts a field comments out the rest of the line.
+pub fn tokenize(line: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let after_space = line[..start].ends_with(char::is_whitespace) || start == 0;
+        let mut end = start + c.len_utf8();
+
+        let kind = match c {
+            _ if c.is_whitespace() => continue,
+            '.' if after_space => {
+                end = line.len();
+                while chars.next().is_some() {}
+                TokenKind::Comment
+            }
+            'C' | 'X' | 'c' | 'x' if matches!(chars.peek(), Some((_, '\''))) => {
+                chars.next();
+                end = closing_quote(line, start, &mut chars)?;
+                TokenKind::Constant
+            }
+            _ if c.is_ascii_alphanumeric() || c == '$' || c == '_' => {
+                while let Some((i, c)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '$' || *c == '_')
+                {
+                    end = i + c.len_utf8();
+                }
+                match line[start..end].chars().all(|c| c.is_ascii_digit()) {
+                    true => TokenKind::Number,
+                    false => TokenKind::Symbol,
+                }
+            }
+            ',' => TokenKind::Comma,
+            '+' | '-' | '*' | '/' | '(' | ')' | '#' | '@' | '=' => TokenKind::Operator,
+            _ => return Err(anyhow::anyhow!("unexpected `{}`", c)),
+        };
+
+        tokens.push(Token::new(kind, &line[start..end], start));
+    }
+
+    Ok(tokens)
+}
+
+/// End of the constant starting at `start`, just past the quote that closes it.
+fn closing_quote(
+    line: &str,
+    start: usize,
+    chars: &mut Peekable<CharIndices>,
+) -> anyhow::Result<usize> {
+    match chars.find(|(_, c)| *c == '\'') {
+        Some((i, _)) => Ok(i + 1),
+        None => Err(anyhow::anyhow!(
+            "unterminated constant `{}`",
+            &line[start..]
+        )),
+    }
+}
+
+/// Uppercases mnemonics, symbols and hex constants in `line`, leaving the contents of
+/// character constants and comments as they are.
+pub fn fold_case(line: &str) -> anyhow::Result<String> {
+    let mut folded = line.to_owned();
+
+    for token in tokenize(line)? {
+        let (start, end) = token.span;
+        let text = match token.kind {
+            TokenKind::Comment => continue,
+            TokenKind::Constant if token.text[..1].eq_ignore_ascii_case("C") => {
+                format!("C{}", &token.text[1..])
+            }
+            _ => token.text.to_ascii_uppercase(),
+        };
+        folded.replace_range(start..end, &text);
+    }
+
+    Ok(folded)
+}
+
+/// Groups tokens that are not separated by whitespace into the fields of `line`, leaving
+/// out comments.
+pub fn split_fields<'a>(line: &'a str, tokens: &[Token]) -> Vec<&'a str> {
+    let mut fields: Vec<(usize, usize)> = Vec::new();
+
+    for token in tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Comment)
+    {
+        match fields.last_mut() {
+            Some(field) if field.1 == token.span.0 => field.1 = token.span.1,
+            _ => fields.push(token.span),
+        }
+    }
+
+    fields
+        .into_iter()
+        .map(|(start, end)| &line[start..end])
+        .collect()
+}
+
+/// Statement columns of a line in fixed format, without the comment after column 35. A `.`
+/// in column 1 comments out the whole line.
+pub fn fixed_statement(line: &str) -> &str {
+    match line.starts_with('.') {
+        true => "",
+        false => column(line, 0, 35).trim_end(),
+    }
+}
+
+/// Splits a line in fixed format into its label, opcode and operand columns, trimmed. The
+/// blank columns 9 and 16 are read with the opcode and operand, for `+` and `#` or `@`
+/// prefixes written there.
+pub fn split_columns(line: &str) -> [&str; 3] {
+    [
+        column(line, 0, 8).trim(),
+        column(line, 8, 15).trim(),
+        column(line, 15, 35).trim(),
+    ]
+}
+
+/// Characters `start..end` of `line`, or as many of them as it has.
+fn column(line: &str, start: usize, end: usize) -> &str {
+    let offset = |n| line.char_indices().nth(n).map_or(line.len(), |(i, _)| i);
+    &line[offset(start)..offset(end)]
+}
+
+/// Spans of the whitespace separated fields of `line` up to a `.` comment, found without
+/// lexing them, so a comment may hold any text. Quoted strings and parenthesized groups
+/// are kept whole, as in `C'A B'` and `(&X EQ 1)`, and one left open runs to the end.
+pub fn field_spans(line: &str) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let mut start = None;
+    let mut quoted = false;
+    let mut depth = 0;
+    let mut before = (' ', ' ');
+
+    for (i, c) in line.char_indices() {
+        if quoted {
+            quoted = c != '\'';
+        } else if c.is_whitespace() && depth == 0 {
+            if let Some(start) = start.take() {
+                spans.push((start, i));
+            }
+        } else {
+            if start.is_none() && c == '.' {
+                return spans;
+            }
+            start.get_or_insert(i);
+
+            // A quote opens a string after `C` or `X` starting a token, or after punctuation
+            let opens = |c: char| !c.is_ascii_alphanumeric() && !"&$_".contains(c);
+            match c {
+                '\'' if opens(before.1) || "CXcx".contains(before.1) && opens(before.0) => {
+                    quoted = true
+                }
+                '(' => depth += 1,
+                ')' if depth > 0 => depth -= 1,
+                _ => {}
+            }
+        }
+        before = (before.1, c);
+    }
+
+    if let Some(start) = start {
+        spans.push((start, line.len()));
+    }
+    spans
+}
+
+/// Splits the tokens of a comma separated operand, such as `BUFFER,X` or EXTREF lists,
+/// into its items.
+pub fn split_operands(tokens: &[Token]) -> Vec<&[Token]> {
+    tokens
+        .split(|token| token.kind == TokenKind::Comma)
+        .collect()
+}
+
+/// Symbols of an EXTDEF or EXTREF list, or `None` if an item is not a single symbol.
+pub fn symbol_list(tokens: &[Token]) -> Option<Vec<&str>> {
+    split_operands(tokens)
+        .into_iter()
+        .map(|item| match item {
+            [token] if token.kind == TokenKind::Symbol => Some(token.text.as_str()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let line = "MSG\tBYTE\tC'HELLO, WORLD'\t. greeting";
+        let tokens = tokenize(line).unwrap();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| (token.kind, token.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenKind::Symbol, "MSG"),
+                (TokenKind::Symbol, "BYTE"),
+                (TokenKind::Constant, "C'HELLO, WORLD'"),
+                (TokenKind::Comment, ". greeting"),
+            ]
+        );
+        assert_eq!(tokens[2].span, (9, 24));
+        assert_eq!(
+            split_fields(line, &tokens),
+            vec!["MSG", "BYTE", "C'HELLO, WORLD'"]
+        );
+
+        let line = "\t+LDA\t=X'05',X";
+        let tokens = tokenize(line).unwrap();
+        assert_eq!(
+            tokens
+                .iter()
+                .map(|token| (token.kind, token.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (TokenKind::Operator, "+"),
+                (TokenKind::Symbol, "LDA"),
+                (TokenKind::Operator, "="),
+                (TokenKind::Constant, "X'05'"),
+                (TokenKind::Comma, ","),
+                (TokenKind::Symbol, "X"),
+            ]
+        );
+        assert_eq!(split_fields(line, &tokens), vec!["+LDA", "=X'05',X"]);
+
+        assert_eq!(
+            tokenize("LEN\tEQU\tBUFEND-BUFFER*2")
+                .unwrap()
+                .iter()
+                .map(|token| token.kind)
+                .collect::<Vec<_>>(),
+            vec![
+                TokenKind::Symbol,
+                TokenKind::Symbol,
+                TokenKind::Symbol,
+                TokenKind::Operator,
+                TokenKind::Symbol,
+                TokenKind::Operator,
+                TokenKind::Number,
+            ]
+        );
+
+        assert!(tokenize("\tBYTE\tC'EOF").is_err());
+        assert!(tokenize("\tLDA\t&X").is_err());
+    }
+
+    #[test]
+    fn test_field_spans() {
+        let fields = |line| {
+            field_spans(line)
+                .into_iter()
+                .map(|(start, end)| &line[start..end])
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            fields("FIRST STL RETADR SAVE CALLER'S ADDRESS; SEE NOTE"),
+            vec!["FIRST", "STL", "RETADR", "SAVE", "CALLER'S", "ADDRESS;", "SEE", "NOTE"]
+        );
+        assert_eq!(
+            fields("\tIF\t(&DEV EQ '0 5')\t. output device"),
+            vec!["IF", "(&DEV EQ '0 5')"]
+        );
+        assert_eq!(
+            fields("MSG\tBYTE\tc'A, B'\tTEXT"),
+            vec!["MSG", "BYTE", "c'A, B'", "TEXT"]
+        );
+        assert_eq!(fields("\tLDA\t=X'05',X"), vec!["LDA", "=X'05',X"]);
+        assert_eq!(fields("\tBYTE\tC'EOF"), vec!["BYTE", "C'EOF"]);
+        assert!(fields(". comment line").is_empty());
+    }
+
+    #[test]
+    fn test_fold_case() {
+        assert_eq!(
+            fold_case("msg\tbyte\tc'Hello'\t. Say hi").unwrap(),
+            "MSG\tBYTE\tC'Hello'\t. Say hi"
+        );
+        assert_eq!(fold_case("\tlda\t=x'f1',x").unwrap(), "\tLDA\t=X'F1',X");
+    }
+
+    #[test]
+    fn test_split_columns() {
+        let line = "FIRST    STL    RETADR             SAVE RETURN ADDRESS, IT'S NEEDED";
+        assert_eq!(fixed_statement(line), "FIRST    STL    RETADR");
+        assert_eq!(
+            split_columns(fixed_statement(line)),
+            ["FIRST", "STL", "RETADR"]
+        );
+        assert_eq!(
+            split_columns("        +JSUB  RDREC"),
+            ["", "+JSUB", "RDREC"]
+        );
+        assert_eq!(
+            split_columns("EOF      BYTE   C'E O F'"),
+            ["EOF", "BYTE", "C'E O F'"]
+        );
+        assert_eq!(fixed_statement(".        COMMENT LINE"), "");
+    }
+
+    #[test]
+    fn test_split_operands() {
+        let texts = |operand| {
+            split_operands(&tokenize(operand).unwrap())
+                .iter()
+                .map(|item| item.iter().map(|token| token.text.as_str()).collect())
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(texts("BUFFER,X"), vec!["BUFFER", "X"]);
+        assert_eq!(texts("C'A,B'"), vec!["C'A,B'"]);
+        assert_eq!(texts("LENGTH+3,X"), vec!["LENGTH+3", "X"]);
+
+        let tokens = tokenize("RDREC,WRREC").unwrap();
+        assert_eq!(symbol_list(&tokens), Some(vec!["RDREC", "WRREC"]));
+        assert_eq!(symbol_list(&tokenize("RDREC,3").unwrap()), None);
+        assert_eq!(symbol_list(&tokenize("RDREC+1").unwrap()), None);
+    }
+}
@@ -1,48 +1,87 @@
 use std::{
     fs::{self, File},
     io::{BufRead, BufReader, Write},
+    path::Path,
 };
 
 pub mod instructions;
 pub mod loader;
+pub mod options;
+pub mod preprocessor;
 
-pub fn parse_sic(source: BufReader<File>) {
-    let pass1 = instructions::pass1(source.lines().map(|l| l.unwrap()));
-
-    match pass1 {
-        Ok((instructions, symtab)) => {
-            // Write loc table
-            let mut loc_file = fs::File::create("loc.txt").unwrap();
-            for ins in &instructions {
-                writeln!(loc_file, "{}", ins).unwrap();
-            }
-
-            let objcodes = instructions::pass2(&instructions, &symtab);
-
-            match objcodes {
-                Ok(objcodes) => {
-                    // Write output table
-                    let mut output_file = fs::File::create("output.txt").unwrap();
-                    for (ins, code) in instructions.iter().zip(&objcodes) {
-                        writeln!(output_file, "{}\t{}", ins, code).unwrap();
-                    }
-
-                    let objectcode = instructions::format_objcode(&instructions, objcodes);
-
-                    // Write objectcode code
-                    let mut objectcode_file = fs::File::create("objectcode.txt").unwrap();
-                    for line in &objectcode {
-                        writeln!(objectcode_file, "{}", line).unwrap();
-                    }
-                }
-                Err(error) => {
-                    println!("{}", error);
-                }
-            }
+use instructions::SourceFormat;
+use options::Options;
+
+/// Assembles `source`, writing the tables and object program. The errors of all stages are
+/// returned together in place of the listing and object program.
+pub fn parse_sic(source: BufReader<File>, options: &Options) -> anyhow::Result<()> {
+    let (mut source, mut errors) = preprocessor::include_files(
+        source.lines().map(|l| l.unwrap()),
+        Path::new(&options.input),
+    );
+
+    // Comments after the fixed columns hold arbitrary text, so only the statement columns
+    // are preprocessed
+    if options.format == SourceFormat::Fixed {
+        for line in &mut source {
+            line.text = instructions::fixed_statement(&line.text).to_owned();
         }
+    }
+
+    let (source, conditional_errors) = preprocessor::resolve_conditionals(source, &options.defines);
+    errors.extend(conditional_errors);
+    let (source, macro_errors) = preprocessor::expand_macros(source, &options.libraries);
+    errors.extend(macro_errors);
+
+    let (instructions, symtab, sections, pass1_errors) =
+        instructions::pass1(source.iter().map(|line| {
+            (
+                line.location.clone(),
+                line.to_string(),
+                line.format(options.format),
+            )
+        }));
+    errors.extend(pass1_errors);
+
+    // Write loc table
+    let mut loc_file = fs::File::create("loc.txt").unwrap();
+    for ins in &instructions {
+        writeln!(loc_file, "{}", ins).unwrap();
+    }
+
+    let (objcodes, modifications, pass2_errors) = instructions::pass2(&instructions, &symtab);
+    errors.extend(pass2_errors);
 
-        Err(error) => {
-            println!("{}", error);
+    // Write output table
+    let mut output_file = fs::File::create("output.txt").unwrap();
+    for (ins, code) in instructions.iter().zip(&objcodes) {
+        writeln!(output_file, "{}\t{}", ins, code).unwrap();
+    }
+
+    if !errors.is_empty() {
+        return Err(instructions::ErrorList::new(errors).into());
+    }
+
+    // Write the expanded source listing when macros are used
+    if source
+        .iter()
+        .any(|line| line.kind == preprocessor::LineKind::Invocation)
+    {
+        let listing = preprocessor::format_listing(&source, &instructions, &objcodes);
+        let mut listing_file = fs::File::create("listing.txt").unwrap();
+        for line in &listing {
+            writeln!(listing_file, "{}", line).unwrap();
         }
-    };
+    }
+
+    let objectcode =
+        instructions::format_objcode(&instructions, objcodes, &modifications, &symtab, &sections);
+
+    // Write objectcode code
+    let mut objectcode_file = fs::File::create("objectcode.txt").unwrap();
+    for line in &objectcode {
+        writeln!(objectcode_file, "{}", line).unwrap();
+    }
+
+    Ok(())
 }
@@ -1,4 +1,19 @@
+use std::{env, process};
+
+use sic_xe_assembler::options::Options;
+
 fn main() {
-    let source = sic_xe_assembler::loader::read_asm_file("input.txt");
-    sic_xe_assembler::parse_sic(source);
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(1);
+        }
+    };
+
+    let source = sic_xe_assembler::loader::read_asm_file(&options.input);
+    if let Err(error) = sic_xe_assembler::parse_sic(source, &options) {
+        println!("{}", error);
+        process::exit(1);
+    }
 }
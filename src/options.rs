@@ -0,0 +1,107 @@
+use std::path::PathBuf;
+
+use crate::instructions::SourceFormat;
+
+/// Command line options, `[-F] [-L library]... [-D name[=value]]... [input]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Options {
+    pub input: String,
+    /// Macro library files or directories, searched in order.
+    pub libraries: Vec<PathBuf>,
+    /// Names for conditional assembly with their values, `1` if not given.
+    pub defines: Vec<(String, String)>,
+    /// `-F` reads the source in fixed columns.
+    pub format: SourceFormat,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            input: "input.txt".to_owned(),
+            libraries: Vec::new(),
+            defines: Vec::new(),
+            format: SourceFormat::Free,
+        }
+    }
+}
+
+impl Options {
+    pub fn parse(args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut input = None;
+        let mut args = args.peekable();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-F" => options.format = SourceFormat::Fixed,
+                "-L" => match args.next() {
+                    Some(library) => options.libraries.push(library.into()),
+                    None => return Err(anyhow::anyhow!("error: missing library after `-L`")),
+                },
+                _ if arg.starts_with("-L") => options.libraries.push(arg[2..].into()),
+                "-D" => match args.next() {
+                    Some(define) => options.defines.push(define_value(&define)),
+                    None => return Err(anyhow::anyhow!("error: missing name after `-D`")),
+                },
+                _ if arg.starts_with("-D") => options.defines.push(define_value(&arg[2..])),
+                _ if arg.starts_with('-') => {
+                    return Err(anyhow::anyhow!("error: unknown option `{}`", arg))
+                }
+                _ if input.is_none() => input = Some(arg),
+                _ => return Err(anyhow::anyhow!("error: unexpected argument `{}`", arg)),
+            }
+        }
+
+        if let Some(input) = input {
+            options.input = input;
+        }
+
+        Ok(options)
+    }
+}
+
+/// Splits `NAME=VALUE`, where a bare `NAME` is defined as `1`.
+fn define_value(define: &str) -> (String, String) {
+    match define.split_once('=') {
+        Some((name, value)) => (name.to_owned(), value.to_owned()),
+        None => (define.to_owned(), "1".to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_options() {
+        let args = |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string()));
+
+        assert_eq!(args(&[]).unwrap(), Options::default());
+        assert_eq!(
+            args(&[
+                "-L",
+                "macros/",
+                "-Lio.asm",
+                "-D",
+                "DEBUG",
+                "-DLEVEL=2",
+                "-F",
+                "copy.asm"
+            ])
+            .unwrap(),
+            Options {
+                input: "copy.asm".to_owned(),
+                libraries: vec!["macros/".into(), "io.asm".into()],
+                defines: vec![
+                    ("DEBUG".to_owned(), "1".to_owned()),
+                    ("LEVEL".to_owned(), "2".to_owned())
+                ],
+                format: SourceFormat::Fixed,
+            }
+        );
+        assert!(args(&["-L"]).is_err());
+        assert!(args(&["-D"]).is_err());
+        assert!(args(&["-X"]).is_err());
+        assert!(args(&["a.asm", "b.asm"]).is_err());
+    }
+}
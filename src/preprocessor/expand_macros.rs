@@ -0,0 +1,452 @@
+use std::{collections::VecDeque, path::PathBuf};
+
+use super::load_library;
+use super::split_list;
+use super::ExpandedLine;
+use super::LineKind;
+use super::MacroDefinition;
+use super::MacroLibrary;
+use crate::instructions::field_spans;
+use crate::instructions::is_mnemonic;
+use crate::instructions::statement_fields;
+use crate::instructions::Location;
+
+/// Deepest macro invocation within expansions, so a macro that keeps invoking itself stops.
+const MAX_NESTING: usize = 100;
+
+/// Collects `MACRO ... MEND` definitions and replaces each invocation with its expansion,
+/// preceded by the invocation as a comment line. Every line keeps the location of the
+/// source line it came from, so generated lines point back into the macro definition.
+/// Mnemonics that are not defined in the source are looked up in the macro `libraries`.
+pub fn expand_macros(
+    source: Vec<ExpandedLine>,
+    libraries: &[PathBuf],
+) -> (Vec<ExpandedLine>, Vec<anyhow::Error>) {
+    let mut source = VecDeque::from(source);
+    let mut errors = Vec::new();
+
+    let library = load_library(libraries).unwrap_or_else(|error| {
+        errors.push(error);
+        MacroLibrary::new()
+    });
+    let mut deftab: Vec<MacroDefinition> = Vec::new();
+    let mut broken: Vec<String> = Vec::new();
+    let mut expanded = Vec::new();
+    let mut expansions = 0;
+    let mut overflowed = false;
+
+    while let Some(source_line) = source.pop_front() {
+        let (line_num, line) = (source_line.location.clone(), source_line.text.as_str());
+        if line.trim_start().starts_with('.') {
+            expanded.push(source_line);
+            continue;
+        }
+
+        let (label, opcode, operand) = match fields(line, &deftab, &library) {
+            Some(fields) => fields,
+            None => {
+                expanded.push(source_line);
+                continue;
+            }
+        };
+
+        match opcode.to_uppercase().as_str() {
+            "MACRO" => match read_definition(&line_num, line, label, operand, &mut source) {
+                Ok(def) => {
+                    deftab.retain(|other| other.name != def.name);
+                    deftab.push(def);
+                }
+                Err(error) => errors.push(error),
+            },
+
+            "MEND" => errors.push(anyhow::anyhow!(
+                "error: MEND without MACRO\n{} | {}",
+                line_num,
+                line
+            )),
+
+            name => {
+                // Library definitions are read the first time they are used, and a bad one
+                // is only reported then. Instructions and directives are never replaced.
+                if broken.iter().any(|other| other == name) {
+                    continue;
+                }
+                if !deftab.iter().any(|def| def.name == name) && !is_mnemonic(name) {
+                    match library.definition(name) {
+                        Some(Ok(def)) => deftab.push(def),
+                        Some(Err(error)) => {
+                            errors.push(error);
+                            broken.push(name.to_owned());
+                            continue;
+                        }
+                        None => {}
+                    }
+                }
+
+                match deftab.iter().find(|def| def.name == name) {
+                    Some(_) if source_line.depth == MAX_NESTING => {
+                        errors.push(anyhow::anyhow!(
+                            "error: macro nesting too deep\n{} | {}",
+                            line_num,
+                            line
+                        ));
+
+                        // The rest of the outermost expansion is dropped with it
+                        while source.front().is_some_and(|line| line.depth > 0) {
+                            source.pop_front();
+                        }
+                    }
+                    Some(def) => {
+                        // Each expansion with `$` labels needs its own prefix
+                        let unique = match def.has_unique_labels() {
+                            true if expansions == 26 * 26 => {
+                                if !overflowed {
+                                    overflowed = true;
+                                    errors.push(anyhow::anyhow!(
+                                        "error: too many macro expansions with `$` labels\n{} | {}",
+                                        line_num,
+                                        line
+                                    ));
+                                }
+                                continue;
+                            }
+                            true => {
+                                expansions += 1;
+                                unique_prefix(expansions - 1)
+                            }
+                            false => String::new(),
+                        };
+
+                        let args = operand.map(split_list).unwrap_or_default();
+
+                        let lines = match def.expand(label, &args, &unique) {
+                            Ok(lines) => lines,
+                            Err(error) => {
+                                errors.push(anyhow::anyhow!(
+                                    "error: {}\n{} | {}",
+                                    error,
+                                    line_num,
+                                    line
+                                ));
+                                continue;
+                            }
+                        };
+
+                        // Expanded lines go back into the source, so they can invoke macros too
+                        for (line_num, line) in lines.into_iter().rev() {
+                            source.push_front(ExpandedLine {
+                                depth: source_line.depth + 1,
+                                ..ExpandedLine::new(line_num, line, LineKind::Generated)
+                            });
+                        }
+                        expanded.push(ExpandedLine {
+                            kind: LineKind::Invocation,
+                            ..source_line.clone()
+                        });
+                    }
+                    None => expanded.push(source_line),
+                }
+            }
+        }
+    }
+
+    (expanded, errors)
+}
+
+/// Reads the definition started by the prototype `line` up to its MEND from `source`.
+pub fn read_definition(
+    line_num: &Location,
+    line: &str,
+    label: Option<&str>,
+    operand: Option<&str>,
+    source: &mut VecDeque<ExpandedLine>,
+) -> anyhow::Result<MacroDefinition> {
+    // The body is read first, so a bad prototype doesn't leave it in the source. Nested
+    // definitions stay in the body until the outer macro is expanded.
+    let mut body = Vec::new();
+    let mut depth = 0;
+    loop {
+        let body_line = match source.pop_front() {
+            Some(line) => line,
+            None => {
+                return Err(anyhow::anyhow!(
+                    "error: missing MEND for `{}`\n{} | {}",
+                    label.unwrap_or_default().to_uppercase(),
+                    line_num,
+                    line
+                ))
+            }
+        };
+
+        match fields(&body_line.text, &[], &MacroLibrary::new())
+            .map(|(_, opcode, _)| opcode.to_uppercase())
+        {
+            Some(opcode) if opcode == "MEND" && depth == 0 => break,
+            Some(opcode) if opcode == "MEND" => depth -= 1,
+            Some(opcode) if opcode == "MACRO" => depth += 1,
+            _ if body_line.text.trim_start().starts_with('.') => continue,
+            _ => {}
+        }
+
+        body.push((body_line.location, body_line.text));
+    }
+
+    let name = match label {
+        Some(label) => label.to_uppercase(),
+        None => {
+            return Err(anyhow::anyhow!(
+                "error: missing macro name\n{} | {}",
+                line_num,
+                line
+            ))
+        }
+    };
+    let params = operand.map(split_list).unwrap_or_default();
+    if let Some(param) = params.iter().find(|param| !param.starts_with('&')) {
+        return Err(anyhow::anyhow!(
+            "error: invalid macro parameter `{}`\n{} | {}",
+            param,
+            line_num,
+            line
+        ));
+    }
+
+    Ok(MacroDefinition::new(name, &params, body))
+}
+
+/// Two letter prefix of the nth expansion, `AA`, `AB`, ... `ZZ`.
+fn unique_prefix(n: usize) -> String {
+    let letter = |i: usize| (b'A' + (i % 26) as u8) as char;
+    format!("{}{}", letter(n / 26), letter(n))
+}
+
+/// Splits a line into label, opcode and operand like a statement, with the macro names
+/// and macro directives known besides the instruction set.
+pub fn fields<'a>(
+    line: &'a str,
+    deftab: &[MacroDefinition],
+    library: &MacroLibrary,
+) -> Option<(Option<&'a str>, &'a str, Option<&'a str>)> {
+    let fields = field_spans(line)
+        .into_iter()
+        .map(|(start, end)| &line[start..end])
+        .collect::<Vec<_>>();
+    if fields.is_empty() {
+        return None;
+    }
+
+    Some(statement_fields(&fields, |name| match name {
+        "MACRO" | "SET" | "IF" | "WHILE" | "IFDEF" | "IFNDEF" => Some(true),
+        "MEND" | "ELSE" | "ENDIF" | "ENDW" => Some(false),
+        _ if deftab.iter().any(|def| def.name == name) => Some(true),
+        _ if library.contains(name) && !is_mnemonic(name) => Some(true),
+        _ => None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_macros_unique_prefixes() {
+        let mut source = vec![
+            "COPY\tSTART\t0",
+            "WAIT\tMACRO",
+            "$LOOP\tTD\t=X'05'",
+            "\tJEQ\t$LOOP",
+            "\tMEND",
+            "CLR\tMACRO",
+            "\tCLEAR\tA",
+            "\tMEND",
+        ];
+        source.extend(vec!["\tCLR"; 1000]);
+        source.extend(["\tWAIT", "\tWAIT", "\tEND\tCOPY"]);
+
+        let source = ExpandedLine::numbered(&source);
+
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            expanded
+                .iter()
+                .filter(|line| line.text.contains('$'))
+                .map(|line| line.text.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "$AALOOP\tTD\t=X'05'",
+                "\tJEQ\t$AALOOP",
+                "$ABLOOP\tTD\t=X'05'",
+                "\tJEQ\t$ABLOOP"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_macros() {
+        let source = [
+            "COPY\tSTART\t0",
+            "RDBUFF\tMACRO\t&INDEV,&BUFADR",
+            "\tTD\t=X'&INDEV'",
+            "\tSTCH\t&BUFADR,X",
+            "\tMEND",
+            "FIRST\tRDBUFF\tF1,BUFFER",
+            "\tEND\tFIRST",
+        ];
+
+        let source = ExpandedLine::numbered(&source);
+
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            expanded,
+            vec![
+                ExpandedLine::new(1, "COPY\tSTART\t0", LineKind::Source),
+                ExpandedLine::new(6, "FIRST\tRDBUFF\tF1,BUFFER", LineKind::Invocation),
+                ExpandedLine {
+                    depth: 1,
+                    ..ExpandedLine::new(3, "FIRST\tTD\t=X'F1'", LineKind::Generated)
+                },
+                ExpandedLine {
+                    depth: 1,
+                    ..ExpandedLine::new(4, "\tSTCH\tBUFFER,X", LineKind::Generated)
+                },
+                ExpandedLine::new(7, "\tEND\tFIRST", LineKind::Source),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_macros_comments_and_constants() {
+        let source = [
+            "WAIT\tMACRO\t&DEV\t. wait for a device",
+            "\tIF\t(&DEV EQ 05)\t. output device",
+            "\tTD\t=X'&DEV'",
+            "\tENDIF",
+            "\tMEND",
+            "MSG\tMACRO\t&TEXT",
+            "\tBYTE\t&TEXT",
+            "\tMEND",
+            "\tWAIT\t05\t. comment",
+            "\tMSG\tC'A B'\tprint it; don't wait",
+            "FIRST\tMSG\tC'X' read the input record",
+        ];
+
+        let source = ExpandedLine::numbered(&source);
+
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert!(errors.is_empty());
+        assert_eq!(
+            expanded,
+            vec![
+                ExpandedLine::new(9, "\tWAIT\t05\t. comment", LineKind::Invocation),
+                ExpandedLine {
+                    depth: 1,
+                    ..ExpandedLine::new(3, "\tTD\t=X'05'", LineKind::Generated)
+                },
+                ExpandedLine::new(
+                    10,
+                    "\tMSG\tC'A B'\tprint it; don't wait",
+                    LineKind::Invocation
+                ),
+                ExpandedLine {
+                    depth: 1,
+                    ..ExpandedLine::new(7, "\tBYTE\tC'A B'", LineKind::Generated)
+                },
+                ExpandedLine::new(
+                    11,
+                    "FIRST\tMSG\tC'X' read the input record",
+                    LineKind::Invocation
+                ),
+                ExpandedLine {
+                    depth: 1,
+                    ..ExpandedLine::new(7, "FIRST\tBYTE\tC'X'", LineKind::Generated)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_macros_collects_errors() {
+        let source = [
+            "\tMACRO\t&DEV",
+            "\tTD\t=X'&DEV'",
+            "\tMEND",
+            "\tMEND",
+            "WAIT\tMACRO\t&DEV",
+            "\tTD\t=X'&DEV'",
+            "\tMEND",
+            "\tWAIT\t&X=1",
+            "\tWAIT\t05",
+        ];
+
+        let source = ExpandedLine::numbered(&source);
+
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert_eq!(
+            expanded,
+            vec![
+                ExpandedLine::new(9, "\tWAIT\t05", LineKind::Invocation),
+                ExpandedLine {
+                    depth: 1,
+                    ..ExpandedLine::new(6, "\tTD\t=X'05'", LineKind::Generated)
+                },
+            ]
+        );
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.to_string().lines().next().unwrap().to_owned())
+                .collect::<Vec<_>>(),
+            vec![
+                "error: missing macro name",
+                "error: MEND without MACRO",
+                "error: unknown keyword parameter `&X`",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expand_macros_nesting_limit() {
+        let source = ["LOOPM\tMACRO", "\tLOOPM", "\tMEND", "\tLOOPM", "\tRSUB"];
+
+        let source = ExpandedLine::numbered(&source);
+
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert_eq!(expanded.len(), MAX_NESTING + 1);
+        assert_eq!(expanded.last().unwrap().text, "\tRSUB");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0]
+            .to_string()
+            .starts_with("error: macro nesting too deep"));
+    }
+
+    #[test]
+    fn test_expand_macros_library() {
+        let dir = std::env::temp_dir().join(format!("sic-library-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("io.asm"),
+            "RD\tMACRO\n\tLDA\t#1\n\tMEND\nRDCH\tMACRO\n\tRD\tINPUT\n\tMEND\n",
+        )
+        .unwrap();
+
+        let source = vec![
+            ExpandedLine::new(1, "\tRD\tINPUT", LineKind::Source),
+            ExpandedLine::new(2, "\tRDCH", LineKind::Source),
+        ];
+
+        // Library macros stand in for undefined mnemonics only
+        let (expanded, errors) = expand_macros(source, std::slice::from_ref(&dir));
+        assert!(errors.is_empty());
+        assert_eq!(
+            expanded
+                .iter()
+                .map(|line| &line.text[..])
+                .collect::<Vec<_>>(),
+            vec!["\tRD\tINPUT", "\tRDCH", "\tRD\tINPUT"]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,77 @@
+use std::fmt;
+
+use crate::instructions::Location;
+use crate::instructions::SourceFormat;
+
+/// Where a line of the expanded source came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Source,
+    /// A macro invocation, kept as a comment in front of its expansion.
+    Invocation,
+    Generated,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpandedLine {
+    /// Line in the source, the definition line for generated lines.
+    pub location: Location,
+    pub text: String,
+    pub kind: LineKind,
+    /// Number of macro expansions the line is nested in.
+    pub depth: usize,
+}
+
+impl fmt::Display for ExpandedLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LineKind::Invocation => write!(f, ".{}", self.text),
+            _ => write!(f, "{}", self.text),
+        }
+    }
+}
+
+impl ExpandedLine {
+    pub fn new(location: impl Into<Location>, text: impl Into<String>, kind: LineKind) -> Self {
+        Self {
+            location: location.into(),
+            text: text.into(),
+            kind,
+            depth: 0,
+        }
+    }
+
+    /// Format the line is read in when the source is in `format`. Macro expansions are laid
+    /// out with tabs rather than in the fixed columns.
+    pub fn format(&self, format: SourceFormat) -> SourceFormat {
+        match self.kind {
+            LineKind::Generated => SourceFormat::Free,
+            _ => format,
+        }
+    }
+
+    /// Source lines numbered from 1.
+    #[cfg(test)]
+    pub fn numbered(source: &[&str]) -> Vec<Self> {
+        source
+            .iter()
+            .enumerate()
+            .map(|(i, line)| Self::new(i + 1, *line, LineKind::Source))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expanded_line() {
+        let line = ExpandedLine::new(6, "FIRST\tRDBUFF\tF1,BUFFER", LineKind::Invocation);
+        assert_eq!(format!("{}", line), ".FIRST\tRDBUFF\tF1,BUFFER");
+
+        let line = ExpandedLine::new(3, "FIRST\tTD\t=X'F1'", LineKind::Generated);
+        assert_eq!(format!("{}", line), "FIRST\tTD\t=X'F1'");
+        assert_eq!(line.format(SourceFormat::Fixed), SourceFormat::Free);
+    }
+}
@@ -0,0 +1,108 @@
+use super::ExpandedLine;
+use super::LineKind;
+use crate::instructions::Statement;
+
+/// Lists the expanded source in the layout of the output table, each invocation as a
+/// comment followed by its expansion. Generated lines are marked with a leading `+`.
+/// Statements are matched to lines by location, so lines without one, such as blank lines,
+/// are left out.
+pub fn format_listing(
+    source: &[ExpandedLine],
+    instructions: &[Statement],
+    objcodes: &[String],
+) -> Vec<String> {
+    let mut listing = Vec::new();
+    let mut instructions = instructions.iter().zip(objcodes).peekable();
+
+    for line in source {
+        if line.kind == LineKind::Invocation {
+            listing.push(format!("\t{}", line));
+            continue;
+        }
+
+        let mark = if line.kind == LineKind::Generated {
+            "+"
+        } else {
+            ""
+        };
+        let of_line = |ins: &Statement| ins.line == line.location;
+
+        // Literal pools have no line of their own and go with the line that placed them,
+        // in front of CSECT or END and after LTORG
+        while let Some((ins, code)) =
+            instructions.next_if(|(ins, _)| of_line(ins) && is_literal(ins))
+        {
+            listing.push(format!("{}\t{}", ins, code));
+        }
+        if let Some((ins, code)) = instructions.next_if(|(ins, _)| of_line(ins)) {
+            listing.push(format!("{}{}\t{}", mark, ins, code));
+        }
+        while let Some((ins, code)) =
+            instructions.next_if(|(ins, _)| of_line(ins) && is_literal(ins))
+        {
+            listing.push(format!("{}\t{}", ins, code));
+        }
+    }
+
+    for (ins, code) in instructions {
+        listing.push(format!("{}\t{}", ins, code));
+    }
+
+    listing
+}
+
+fn is_literal(ins: &Statement) -> bool {
+    ins.label.as_deref() == Some("*") && ins.opcode.starts_with('=')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::{pass1, pass2, SourceFormat};
+    use crate::preprocessor::expand_macros;
+
+    #[test]
+    fn test_format_listing() {
+        let source = [
+            "COPY\tSTART\t0",
+            "WAIT\tMACRO\t&DEV",
+            "$LOOP\tTD\t=X'&DEV'",
+            "\tJEQ\t$LOOP",
+            "\tMEND",
+            ". wait for the device",
+            "",
+            "\tWAIT\tF1",
+            "",
+            "\tLTORG",
+            "\tRSUB",
+            "\tEND\tCOPY",
+        ];
+
+        let source = ExpandedLine::numbered(&source);
+
+        let (expanded, errors) = expand_macros(source, &[]);
+        assert!(errors.is_empty());
+        let (instructions, symtab, _, errors) = pass1(
+            expanded
+                .iter()
+                .map(|line| (line.location.clone(), line.to_string(), SourceFormat::Free)),
+        );
+        assert!(errors.is_empty());
+        let (objcodes, _, errors) = pass2(&instructions, &symtab);
+        assert!(errors.is_empty());
+
+        assert_eq!(
+            format_listing(&expanded, &instructions, &objcodes),
+            vec![
+                "0000\tCOPY\tSTART\t0\t",
+                "\t.\tWAIT\tF1",
+                "+0000\t$AALOOP\tTD\t=X'F1'\tE32003",
+                "+0003\t\tJEQ\t$AALOOP\t332FFA",
+                "0006\t\tLTORG\t\t",
+                "0006\t*\t=X'F1'\t\tF1",
+                "0007\t\tRSUB\t\t4F0000",
+                "000A\t\tEND\tCOPY\t",
+            ]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::ExpandedLine;
+use super::LineKind;
+use crate::instructions::Location;
+
+/// Reads the lines of `source`, the contents of `path`, replacing each `INCLUDE "file"` line
+/// with the lines of that file, found relative to the file including it. Lines of `source`
+/// keep plain line numbers, included lines are located by file and line.
+pub fn include_files(
+    source: impl Iterator<Item = String>,
+    path: &Path,
+) -> (Vec<ExpandedLine>, Vec<anyhow::Error>) {
+    let mut lines = Vec::new();
+    let mut errors = Vec::new();
+    let mut stack = vec![fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())];
+
+    include(source, None, path, &mut stack, &mut lines, &mut errors);
+
+    (lines, errors)
+}
+
+fn include(
+    source: impl Iterator<Item = String>,
+    file: Option<Rc<str>>,
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    lines: &mut Vec<ExpandedLine>,
+    errors: &mut Vec<anyhow::Error>,
+) {
+    for (i, line) in source.enumerate() {
+        let location = Location::new(file.clone(), i + 1);
+
+        let name = match include_name(&line) {
+            Some(name) => name,
+            None => {
+                lines.push(ExpandedLine::new(location, line, LineKind::Source));
+                continue;
+            }
+        };
+
+        let included = path.parent().unwrap_or(Path::new("")).join(name);
+        let text = match fs::read_to_string(&included) {
+            Ok(text) => text,
+            Err(error) => {
+                errors.push(anyhow::anyhow!(
+                    "error: cannot read `{}`: {}\n{} | {}",
+                    included.display(),
+                    error,
+                    location,
+                    line
+                ));
+                continue;
+            }
+        };
+
+        let canonical = fs::canonicalize(&included).unwrap_or_else(|_| included.clone());
+        if stack.contains(&canonical) {
+            errors.push(anyhow::anyhow!(
+                "error: recursive INCLUDE of `{}`\n{} | {}",
+                included.display(),
+                location,
+                line
+            ));
+            continue;
+        }
+
+        stack.push(canonical);
+        include(
+            text.lines().map(str::to_owned),
+            Some(included.display().to_string().into()),
+            &included,
+            stack,
+            lines,
+            errors,
+        );
+        stack.pop();
+    }
+}
+
+/// File name of an `INCLUDE "file"` line, with or without the quotes.
+fn include_name(line: &str) -> Option<&str> {
+    let line = line.trim();
+    let (directive, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    if !directive.eq_ignore_ascii_case("INCLUDE") {
+        return None;
+    }
+
+    let name = name.trim();
+    Some(
+        name.strip_prefix('"')
+            .and_then(|name| name.strip_suffix('"'))
+            .unwrap_or(name),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_include_files() {
+        let dir = std::env::temp_dir().join(format!("sic-include-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("io.asm"), "\tTD\tINDEV\n\tINCLUDE \"data.asm\"\n").unwrap();
+        fs::write(dir.join("data.asm"), "INDEV\tBYTE\tX'F1'\n").unwrap();
+        fs::write(dir.join("loop.asm"), "\tINCLUDE\tloop.asm\n").unwrap();
+
+        let source = ["COPY\tSTART\t0", "\tinclude \"io.asm\"", "\tEND\tCOPY"];
+        let (lines, errors) =
+            include_files(source.iter().map(|l| l.to_string()), &dir.join("input.txt"));
+        assert!(errors.is_empty());
+        assert_eq!(
+            lines
+                .iter()
+                .map(|line| format!("{} | {}", line.location, line))
+                .collect::<Vec<_>>(),
+            vec![
+                "1 | COPY\tSTART\t0".to_owned(),
+                format!("{}:1 | \tTD\tINDEV", dir.join("io.asm").display()),
+                format!("{}:1 | INDEV\tBYTE\tX'F1'", dir.join("data.asm").display()),
+                "3 | \tEND\tCOPY".to_owned(),
+            ]
+        );
+
+        let source = ["\tINCLUDE\tloop.asm", "\tINCLUDE\tmissing.asm", "\tRSUB"];
+        let (lines, errors) =
+            include_files(source.iter().map(|l| l.to_string()), &dir.join("input.txt"));
+        assert_eq!(lines.len(), 1);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0]
+            .to_string()
+            .starts_with("error: recursive INCLUDE"));
+        assert!(errors[1].to_string().starts_with("error: cannot read"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
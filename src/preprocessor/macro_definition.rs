@@ -0,0 +1,489 @@
+use std::fmt;
+
+use super::condition;
+use super::fields;
+use super::list_items;
+use super::number;
+use super::set_value;
+use super::MacroLibrary;
+use crate::instructions::Location;
+
+#[derive(Debug, Clone)]
+pub struct MacroDefinition {
+    pub name: String,
+    /// Parameter names with their defaults, `None` for positional parameters.
+    pub params: Vec<(String, Option<String>)>,
+    /// Body lines with their locations in the source.
+    pub body: Vec<(Location, String)>,
+}
+
+/// Macro-time statements of a body line, anything else is a line to generate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Directive<'a> {
+    Set(&'a str, &'a str),
+    If(&'a str),
+    Else,
+    EndIf,
+    While(&'a str),
+    EndW,
+    Line,
+}
+
+impl fmt::Display for MacroDefinition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let params = self
+            .params
+            .iter()
+            .map(|(name, default)| match default {
+                Some(default) => format!("{}={}", name, default),
+                None => name.to_owned(),
+            })
+            .collect::<Vec<_>>();
+
+        write!(f, "{}\tMACRO\t{}", self.name, params.join(","))
+    }
+}
+
+impl MacroDefinition {
+    /// Creates a definition from the prototype parameters, where `&NAME=VALUE` declares a
+    /// keyword parameter with a default.
+    pub fn new(name: impl Into<String>, params: &[&str], body: Vec<(Location, String)>) -> Self {
+        Self {
+            name: name.into(),
+            params: params
+                .iter()
+                .map(|param| match param.split_once('=') {
+                    Some((name, default)) => (name.to_uppercase(), Some(default.to_owned())),
+                    None => (param.to_uppercase(), None),
+                })
+                .collect(),
+            body,
+        }
+    }
+
+    /// Expands the body with `args`, putting `label` on the first generated line unless that
+    /// one has a label already. Arguments are positional unless given as `NAME=VALUE`, which
+    /// may appear in any order. SET, IF and WHILE statements are evaluated here and only the
+    /// surviving lines are returned. Labels starting with `$` get the `unique` prefix of
+    /// this expansion, so `$LOOP` becomes `$AALOOP`.
+    pub fn expand(
+        &self,
+        label: Option<&str>,
+        args: &[&str],
+        unique: &str,
+    ) -> anyhow::Result<Vec<(Location, String)>> {
+        let mut symbols = self.bind(args)?;
+        let directives = self.directives();
+        let jumps = self.jumps(&directives)?;
+
+        let mut lines = Vec::new();
+        let mut iterations = 0;
+        let mut i = 0;
+
+        while let Some((line_num, line)) = self.body.get(i) {
+            let at = |error: anyhow::Error| anyhow::anyhow!("{}\n{} | {}", error, line_num, line);
+
+            match directives[i] {
+                Directive::Set(name, value) => {
+                    let value = set_value(&substitute(value, &symbols).map_err(at)?).map_err(at)?;
+                    let name = name.to_uppercase();
+                    match symbols.iter_mut().find(|(symbol, _)| *symbol == name) {
+                        Some(symbol) => symbol.1 = value,
+                        None => symbols.push((name, value)),
+                    }
+                }
+                Directive::If(text) | Directive::While(text) => {
+                    if !condition(text, |text| substitute(text, &symbols)).map_err(at)? {
+                        i = jumps[i];
+                    }
+                }
+                // Reached only from a taken IF branch
+                Directive::Else => i = jumps[i],
+                Directive::EndIf => {}
+                Directive::EndW => {
+                    iterations += 1;
+                    if iterations > 10000 {
+                        return Err(at(anyhow::anyhow!("too many WHILE iterations")));
+                    }
+
+                    i = jumps[i];
+                    continue;
+                }
+                Directive::Line => {
+                    let line = substitute(line, &symbols).map_err(at)?;
+                    lines.push((line_num.clone(), unique_labels(&line, unique)));
+                }
+            }
+
+            i += 1;
+        }
+
+        // A first line with a label of its own, or none to take it, leaves the invocation
+        // label on an `EQU *` line
+        if let Some(label) = label {
+            match lines.first_mut() {
+                Some((_, first)) if takes_label(first) => {
+                    *first = format!("{}\t{}", label, first.trim_start())
+                }
+                first => {
+                    let line_num = first
+                        .map(|(line_num, _)| &*line_num)
+                        .or(self.body.first().map(|(line_num, _)| line_num))
+                        .cloned()
+                        .unwrap_or_default();
+                    lines.insert(0, (line_num, format!("{}\tEQU\t*", label)));
+                }
+            }
+        }
+
+        Ok(lines)
+    }
+
+    /// Whether the body has `$` labels, which need a prefix of their own in each expansion.
+    pub fn has_unique_labels(&self) -> bool {
+        self.body.iter().any(|(_, line)| line.contains('$'))
+    }
+
+    /// Values of the parameters for an invocation with `args`.
+    fn bind(&self, args: &[&str]) -> anyhow::Result<Vec<(String, String)>> {
+        let mut symbols = self
+            .params
+            .iter()
+            .map(|(name, default)| (name.to_owned(), default.clone().unwrap_or_default()))
+            .collect::<Vec<_>>();
+
+        let mut positional = self
+            .params
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, default))| default.is_none())
+            .map(|(i, _)| i);
+
+        for arg in args {
+            match keyword(arg) {
+                Some((key, value)) => match position(&symbols, key) {
+                    Some(i) if self.params[i].1.is_some() => symbols[i].1 = value.to_owned(),
+                    _ => return Err(anyhow::anyhow!("unknown keyword parameter `{}`", key)),
+                },
+                None => match positional.next() {
+                    Some(i) => symbols[i].1 = arg.to_string(),
+                    None => return Err(anyhow::anyhow!("too many arguments for `{}`", self.name)),
+                },
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    /// Classifies the body lines. Lines of nested definitions are left for their own
+    /// expansion.
+    fn directives(&self) -> Vec<Directive<'_>> {
+        let mut depth = 0;
+
+        self.body
+            .iter()
+            .map(|(_, line)| {
+                let (label, opcode, operand) =
+                    fields(line, &[], &MacroLibrary::new()).unwrap_or_default();
+
+                match opcode.to_uppercase().as_str() {
+                    "MACRO" => depth += 1,
+                    "MEND" => depth -= 1,
+                    _ if depth > 0 => {}
+                    "SET" => {
+                        if let (Some(label), Some(operand)) = (label, operand) {
+                            return Directive::Set(label, operand);
+                        }
+                    }
+                    "IF" => return Directive::If(operand.unwrap_or_default()),
+                    "ELSE" => return Directive::Else,
+                    "ENDIF" => return Directive::EndIf,
+                    "WHILE" => return Directive::While(operand.unwrap_or_default()),
+                    "ENDW" => return Directive::EndW,
+                    _ => {}
+                }
+
+                Directive::Line
+            })
+            .collect()
+    }
+
+    /// Where control goes from each IF (its ELSE or ENDIF), ELSE (its ENDIF), WHILE (its
+    /// ENDW) and ENDW (its WHILE).
+    fn jumps(&self, directives: &[Directive]) -> anyhow::Result<Vec<usize>> {
+        let mut jumps = vec![0; directives.len()];
+        let mut open: Vec<usize> = Vec::new();
+
+        for (i, directive) in directives.iter().enumerate() {
+            let at = |message: &str| {
+                let (line_num, line) = &self.body[i];
+                anyhow::anyhow!("{}\n{} | {}", message, line_num, line)
+            };
+
+            match directive {
+                Directive::If(_) | Directive::While(_) => open.push(i),
+                Directive::Else => match open.pop() {
+                    Some(j) if matches!(directives[j], Directive::If(_)) => {
+                        jumps[j] = i;
+                        open.push(i);
+                    }
+                    _ => return Err(at("ELSE without IF")),
+                },
+                Directive::EndIf => match open.pop() {
+                    Some(j) if matches!(directives[j], Directive::If(_) | Directive::Else) => {
+                        jumps[j] = i
+                    }
+                    _ => return Err(at("ENDIF without IF")),
+                },
+                Directive::EndW => match open.pop() {
+                    Some(j) if matches!(directives[j], Directive::While(_)) => {
+                        jumps[j] = i;
+                        jumps[i] = j;
+                    }
+                    _ => return Err(at("ENDW without WHILE")),
+                },
+                Directive::Set(..) | Directive::Line => {}
+            }
+        }
+
+        match open.pop() {
+            Some(i) => {
+                let (line_num, line) = &self.body[i];
+                Err(anyhow::anyhow!(
+                    "missing ENDIF or ENDW\n{} | {}",
+                    line_num,
+                    line
+                ))
+            }
+            None => Ok(jumps),
+        }
+    }
+}
+
+/// Replaces each `&NAME` in `line` with its value. `&NAME[N]` takes the Nth item of a list
+/// argument and `%NITEMS(...)` counts its items.
+fn substitute(line: &str, symbols: &[(String, String)]) -> anyhow::Result<String> {
+    let mut result = String::new();
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c != '&' && c != '%' {
+            result.push(c);
+            continue;
+        }
+
+        let mut end = start + 1;
+        while let Some((i, _)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric()) {
+            end = i + 1;
+        }
+        let name = &line[start..end];
+
+        let bracket = match (c, chars.peek()) {
+            ('&', Some((_, '['))) => Some(('[', ']')),
+            ('%', Some((_, '('))) => Some(('(', ')')),
+            _ => None,
+        };
+        let arg = match bracket {
+            Some((open, close)) => {
+                let from = chars.next().unwrap().0 + 1;
+                let mut depth = 1;
+                let to = loop {
+                    match chars.next() {
+                        Some((_, c)) if c == open => depth += 1,
+                        Some((i, c)) if c == close => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break i;
+                            }
+                        }
+                        Some(_) => {}
+                        None => {
+                            return Err(anyhow::anyhow!("missing `{}` after `{}`", close, name))
+                        }
+                    }
+                };
+                Some(substitute(&line[from..to], symbols)?)
+            }
+            None => None,
+        };
+
+        match (c, arg) {
+            ('&', arg) => match (position(symbols, name), arg) {
+                (Some(i), Some(index)) => {
+                    let index = number(&index)
+                        .ok_or_else(|| anyhow::anyhow!("invalid subscript `{}`", index))?;
+                    let items = list_items(&symbols[i].1);
+                    if let Some(item) = usize::try_from(index - 1)
+                        .ok()
+                        .and_then(|index| items.get(index))
+                    {
+                        result.push_str(item);
+                    }
+                }
+                (Some(i), None) => result.push_str(&symbols[i].1),
+                (None, Some(arg)) => result.push_str(&format!("{}[{}]", name, arg)),
+                (None, None) => result.push_str(name),
+            },
+            ('%', Some(arg)) if name.eq_ignore_ascii_case("%NITEMS") => {
+                result.push_str(&list_items(&arg).len().to_string())
+            }
+            (_, Some(_)) => return Err(anyhow::anyhow!("unknown function `{}`", name)),
+            (_, None) => result.push_str(name),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Inserts `unique` after each `$` that starts a label, leaving character constants alone.
+fn unique_labels(line: &str, unique: &str) -> String {
+    let mut result = String::new();
+    let mut chars = line.chars().peekable();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        result.push(c);
+        match c {
+            '\'' => quoted = !quoted,
+            '$' if !quoted && chars.peek().is_some_and(|c| c.is_ascii_alphanumeric()) => {
+                result.push_str(unique)
+            }
+            _ => {}
+        }
+    }
+
+    result
+}
+
+/// Whether a generated line is a statement without a label.
+fn takes_label(line: &str) -> bool {
+    let statement = line.trim_start();
+    line.starts_with(char::is_whitespace) && !statement.is_empty() && !statement.starts_with('.')
+}
+
+/// Index of the symbol `name`, with or without its leading `&`.
+fn position(symbols: &[(String, String)], name: &str) -> Option<usize> {
+    let name = name.strip_prefix('&').unwrap_or(name);
+    symbols
+        .iter()
+        .position(|(symbol, _)| symbol[1..].eq_ignore_ascii_case(name))
+}
+
+/// Splits a `NAME=VALUE` argument. Literal arguments such as `=X'05'` are positional.
+fn keyword(arg: &str) -> Option<(&str, &str)> {
+    arg.split_once('=').filter(|(key, _)| {
+        let key = key.strip_prefix('&').unwrap_or(key);
+        !key.is_empty() && key.chars().all(|c| c.is_ascii_alphanumeric())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(lines: &[&str]) -> Vec<(Location, String)> {
+        lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| ((i + 2).into(), line.to_string()))
+            .collect()
+    }
+
+    fn lines(lines: Vec<(Location, String)>) -> Vec<String> {
+        lines.into_iter().map(|(_, line)| line).collect()
+    }
+
+    #[test]
+    fn test_macro_definition() {
+        let definition = MacroDefinition::new(
+            "WRBUFF",
+            &["&OUTDEV", "&BUFADR"],
+            body(&["\tTD\t=X'&OUTDEV'", "\tLDA\t&BUFADR"]),
+        );
+        assert_eq!(format!("{}", definition), "WRBUFF\tMACRO\t&OUTDEV,&BUFADR");
+        assert_eq!(
+            definition.expand(Some("LOOP"), &["05"], "AA").unwrap(),
+            vec![
+                (2.into(), "LOOP\tTD\t=X'05'".to_owned()),
+                (3.into(), "\tLDA\t".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_macro_definition_keywords() {
+        let definition = MacroDefinition::new(
+            "RDBUFF",
+            &["&BUFADR", "&INDEV=F1", "&RECLTH="],
+            body(&["\tTD\t=X'&INDEV'", "\tSTCH\t&BUFADR,X"]),
+        );
+        assert_eq!(
+            format!("{}", definition),
+            "RDBUFF\tMACRO\t&BUFADR,&INDEV=F1,&RECLTH="
+        );
+        assert_eq!(
+            lines(definition.expand(None, &["BUFFER"], "AA").unwrap()),
+            vec!["\tTD\t=X'F1'", "\tSTCH\tBUFFER,X"]
+        );
+        assert_eq!(
+            lines(
+                definition
+                    .expand(None, &["INDEV=05", "BUFFER"], "AA")
+                    .unwrap()
+            ),
+            vec!["\tTD\t=X'05'", "\tSTCH\tBUFFER,X"]
+        );
+        assert!(definition.expand(None, &["OUTDEV=05"], "AA").is_err());
+        assert!(definition
+            .expand(None, &["BUFFER", "LENGTH"], "AA")
+            .is_err());
+    }
+
+    #[test]
+    fn test_macro_definition_conditions() {
+        let definition = MacroDefinition::new(
+            "RDBUFF",
+            &["&INDEV", "&EOR"],
+            body(&[
+                "&EORCT\tSET\t%NITEMS(&EOR)",
+                "\tIF\t(&EORCT GT 0)",
+                "&CTR\tSET\t1",
+                "\tWHILE\t(&CTR LE &EORCT)",
+                "\tCOMP\t=X'0000&EOR[&CTR]'",
+                "&CTR\tSET\t&CTR+1",
+                "\tENDW",
+                "\tELSE",
+                "\tTD\t=X'&INDEV'",
+                "\tENDIF",
+            ]),
+        );
+        assert_eq!(
+            definition
+                .expand(None, &["F1", "(00,03,04)"], "AA")
+                .unwrap(),
+            vec![
+                (6.into(), "\tCOMP\t=X'000000'".to_owned()),
+                (6.into(), "\tCOMP\t=X'000003'".to_owned()),
+                (6.into(), "\tCOMP\t=X'000004'".to_owned()),
+            ]
+        );
+        assert_eq!(
+            lines(definition.expand(None, &["F1"], "AA").unwrap()),
+            vec!["\tTD\t=X'F1'"]
+        );
+
+        let definition =
+            MacroDefinition::new("WAIT", &[], body(&["$LOOP\tTD\t=C'$X'", "\tJEQ\t$LOOP"]));
+        assert_eq!(
+            lines(definition.expand(None, &[], "AB").unwrap()),
+            vec!["$ABLOOP\tTD\t=C'$X'", "\tJEQ\t$ABLOOP"]
+        );
+        assert_eq!(
+            lines(definition.expand(Some("FIRST"), &[], "AC").unwrap()),
+            vec!["FIRST\tEQU\t*", "$ACLOOP\tTD\t=C'$X'", "\tJEQ\t$ACLOOP"]
+        );
+
+        let definition = MacroDefinition::new("BAD", &[], body(&["\tIF\t(1 EQ 1)"]));
+        assert!(definition.expand(None, &[], "AA").is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+
+use crate::instructions::evaluate;
+use crate::instructions::tokenize;
+use crate::instructions::SymbolKind;
+
+/// Evaluates a `(LHS OP RHS)` condition, where `OP` is one of `EQ`, `NE`, `LT`, `LE`, `GT`
+/// and `GE`. Each side goes through `substitute` first, and both sides are compared as
+/// numbers if they are numbers and as strings otherwise, with `'...'` quoting a string.
+pub fn condition(
+    text: &str,
+    substitute: impl Fn(&str) -> anyhow::Result<String>,
+) -> anyhow::Result<bool> {
+    let inner = text
+        .trim()
+        .strip_prefix('(')
+        .and_then(|text| text.strip_suffix(')'))
+        .unwrap_or(text);
+
+    let (lhs, op, rhs) = match inner.split_whitespace().collect::<Vec<_>>()[..] {
+        [lhs, op, rhs] => (lhs, op, rhs),
+        _ => return Err(anyhow::anyhow!("invalid condition `{}`", text)),
+    };
+
+    let lhs = substitute(lhs)?;
+    let rhs = substitute(rhs)?;
+    let ordering = match (number(&lhs), number(&rhs)) {
+        (Some(lhs), Some(rhs)) => lhs.cmp(&rhs),
+        _ => unquote(&lhs).cmp(unquote(&rhs)),
+    };
+
+    match op.to_uppercase().as_str() {
+        "EQ" => Ok(ordering == Ordering::Equal),
+        "NE" => Ok(ordering != Ordering::Equal),
+        "LT" => Ok(ordering == Ordering::Less),
+        "LE" => Ok(ordering != Ordering::Greater),
+        "GT" => Ok(ordering == Ordering::Greater),
+        "GE" => Ok(ordering != Ordering::Less),
+        _ => Err(anyhow::anyhow!("invalid relational operator `{}`", op)),
+    }
+}
+
+/// Value of a SET statement, either a `'...'` string or an absolute expression.
+pub fn set_value(text: &str) -> anyhow::Result<String> {
+    if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        return Ok(unquote(text).to_owned());
+    }
+
+    match number(text) {
+        Some(value) => Ok(value.to_string()),
+        None => Err(anyhow::anyhow!("invalid SET value `{}`", text)),
+    }
+}
+
+/// Value of an absolute expression such as `&CTR+1` after substitution.
+pub fn number(text: &str) -> Option<isize> {
+    match tokenize(text).and_then(|tokens| evaluate(&tokens, 0, &[])) {
+        Ok(value) if value.kind() == SymbolKind::Absolute => Some(value.value),
+        _ => None,
+    }
+}
+
+/// Splits `text` at commas that are not inside parentheses or quotes.
+pub fn split_list(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut start = 0;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            '\'' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            ',' if !quoted && depth == 0 => {
+                items.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+
+    items.push(&text[start..]);
+    items
+}
+
+/// Items of a `(A,B,C)` list argument. Any other value is a list of one item.
+pub fn list_items(value: &str) -> Vec<&str> {
+    match value.strip_prefix('(').and_then(|v| v.strip_suffix(')')) {
+        Some(inner) => split_list(inner),
+        None if value.is_empty() => Vec::new(),
+        None => vec![value],
+    }
+}
+
+fn unquote(text: &str) -> &str {
+    text.strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+        .unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_expression() {
+        let same = |text: &str| Ok(text.to_owned());
+        assert!(condition("(F1 NE '')", same).unwrap());
+        assert!(condition("('' EQ '')", same).unwrap());
+        assert!(condition("(2 LE 1+1)", same).unwrap());
+        assert!(!condition("(10 LT 9)", same).unwrap());
+        assert!(condition("(ABC GT ABB)", same).unwrap());
+        assert!(condition("(1 XX 2)", same).is_err());
+        assert!(condition("(1 EQ)", same).is_err());
+
+        assert_eq!(set_value("1+1").unwrap(), "2");
+        assert_eq!(set_value("'F1'").unwrap(), "F1");
+        assert!(set_value("F1").is_err());
+
+        assert_eq!(split_list("A,(B,C),C'D,E'"), vec!["A", "(B,C)", "C'D,E'"]);
+        assert_eq!(list_items("(00,03,04)"), vec!["00", "03", "04"]);
+        assert_eq!(list_items("F1"), vec!["F1"]);
+        assert!(list_items("").is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+use std::{collections::VecDeque, fs, path::PathBuf, rc::Rc};
+
+use super::fields;
+use super::read_definition;
+use super::ExpandedLine;
+use super::LineKind;
+use super::MacroDefinition;
+use crate::instructions::Location;
+
+/// Macro definitions of the library files, indexed by name. A definition is only read
+/// when it is first used, so errors in the others don't get in the way.
+#[derive(Debug, Default)]
+pub struct MacroLibrary {
+    /// Lines of each library file.
+    files: Vec<Vec<ExpandedLine>>,
+    /// Name of each definition with its file and the index of its prototype line.
+    index: Vec<(String, usize, usize)>,
+}
+
+impl MacroLibrary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Indexes the definitions of a library source, which holds nothing but definitions
+    /// and comments. Earlier definitions win over later ones of the same name.
+    pub fn add(&mut self, text: &str, file: Option<Rc<str>>) {
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| {
+                ExpandedLine::new(Location::new(file.clone(), i + 1), line, LineKind::Source)
+            })
+            .collect::<Vec<_>>();
+
+        let mut depth = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if line.text.trim_start().starts_with('.') {
+                continue;
+            }
+
+            match fields(&line.text, &[], &Self::new()) {
+                Some((label, opcode, _)) if opcode.eq_ignore_ascii_case("MACRO") => {
+                    if let (0, Some(name)) = (depth, label) {
+                        self.index.push((name.to_uppercase(), self.files.len(), i));
+                    }
+                    depth += 1;
+                }
+                Some((_, opcode, _)) if opcode.eq_ignore_ascii_case("MEND") && depth > 0 => {
+                    depth -= 1
+                }
+                _ => {}
+            }
+        }
+
+        self.files.push(lines);
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.index.iter().any(|(other, _, _)| other == name)
+    }
+
+    /// Reads the definition of `name`, if the library has one.
+    pub fn definition(&self, name: &str) -> Option<anyhow::Result<MacroDefinition>> {
+        let (_, file, start) = self.index.iter().find(|(other, _, _)| other == name)?;
+        let prototype = &self.files[*file][*start];
+        let mut source = self.files[*file][start + 1..]
+            .iter()
+            .cloned()
+            .collect::<VecDeque<_>>();
+
+        let (label, _, operand) = fields(&prototype.text, &[], &Self::new())?;
+        Some(read_definition(
+            &prototype.location,
+            &prototype.text,
+            label,
+            operand,
+            &mut source,
+        ))
+    }
+}
+
+/// Indexes the macro definitions in each library file, or in every file of a library
+/// directory, in the order given.
+pub fn load_library(paths: &[PathBuf]) -> anyhow::Result<MacroLibrary> {
+    let mut library = MacroLibrary::new();
+
+    for path in paths {
+        let files = if path.is_dir() {
+            let mut files = fs::read_dir(path)
+                .and_then(|entries| {
+                    entries
+                        .map(|entry| entry.map(|entry| entry.path()))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .map_err(|error| {
+                    anyhow::anyhow!(
+                        "error: cannot read macro library `{}`: {}",
+                        path.display(),
+                        error
+                    )
+                })?;
+            files.retain(|file| file.is_file());
+            files.sort();
+            files
+        } else {
+            vec![path.clone()]
+        };
+
+        for file in files {
+            let text = fs::read_to_string(&file).map_err(|error| {
+                anyhow::anyhow!(
+                    "error: cannot read macro library `{}`: {}",
+                    file.display(),
+                    error
+                )
+            })?;
+
+            library.add(&text, Some(file.display().to_string().into()));
+        }
+    }
+
+    Ok(library)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_macro_library() {
+        let mut library = MacroLibrary::new();
+        library.add(
+            ". standard I/O macros\n\
+             RDCH\tMACRO\t&DEV\n\
+             \tTD\t=X'&DEV'\n\
+             \tRD\t=X'&DEV'\n\
+             \tMEND\n\
+             \n\
+             WRCH\tMACRO\t&DEV=05\n\
+             \tWD\t=X'&DEV'\n\
+             \tMEND\n\
+             BROKEN\tMACRO\tDEV\n\
+             \tMEND\n\
+             LOOSE\tMACRO\n\
+             \tRSUB\n",
+            Some("io.asm".into()),
+        );
+        library.add("WRCH\tMACRO\n\tMEND\n", None);
+
+        assert_eq!(
+            library.definition("RDCH").unwrap().unwrap().to_string(),
+            "RDCH\tMACRO\t&DEV"
+        );
+        let definition = library.definition("WRCH").unwrap().unwrap();
+        assert_eq!(definition.to_string(), "WRCH\tMACRO\t&DEV=05");
+        assert_eq!(
+            definition.body,
+            vec![(
+                Location::new(Some("io.asm".into()), 8),
+                "\tWD\t=X'&DEV'".to_owned()
+            )]
+        );
+
+        // Bad definitions are only reported when used
+        assert!(library.contains("BROKEN"));
+        assert!(library.definition("BROKEN").unwrap().is_err());
+        assert!(library.definition("LOOSE").unwrap().is_err());
+        assert!(library.definition("LDA").is_none());
+    }
+}
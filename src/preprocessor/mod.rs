@@ -0,0 +1,25 @@
+mod expand_macros;
+mod expanded_line;
+mod format_listing;
+mod include_files;
+mod macro_definition;
+mod macro_expression;
+mod macro_library;
+mod resolve_conditionals;
+
+pub use expand_macros::expand_macros;
+use expand_macros::fields;
+use expand_macros::read_definition;
+pub use expanded_line::ExpandedLine;
+pub use expanded_line::LineKind;
+pub use format_listing::format_listing;
+pub use include_files::include_files;
+use macro_definition::MacroDefinition;
+use macro_expression::condition;
+use macro_expression::list_items;
+use macro_expression::number;
+use macro_expression::set_value;
+use macro_expression::split_list;
+use macro_library::load_library;
+use macro_library::MacroLibrary;
+pub use resolve_conditionals::resolve_conditionals;
@@ -0,0 +1,231 @@
+use super::condition;
+use super::fields;
+use super::ExpandedLine;
+use super::MacroLibrary;
+use crate::instructions::evaluate;
+use crate::instructions::tokenize;
+use crate::instructions::SymbolKind;
+use crate::instructions::SymbolMapping;
+
+/// State of an open IFDEF, IFNDEF or IF block.
+struct Block {
+    /// Whether the enclosing lines are assembled.
+    outer: bool,
+    /// Whether the current branch is assembled.
+    active: bool,
+    /// Whether ELSE has been seen.
+    otherwise: bool,
+    /// Index of the opening line, for a missing ENDIF.
+    start: usize,
+}
+
+/// Drops the lines disabled by top-level `IFDEF name`, `IFNDEF name` and `IF expr` blocks,
+/// with optional ELSE and closing ENDIF. Conditions are evaluated over the command line
+/// `defines`, and macro definitions are passed through for their own IF statements. A
+/// condition that can't be evaluated is taken as false.
+pub fn resolve_conditionals(
+    source: Vec<ExpandedLine>,
+    defines: &[(String, String)],
+) -> (Vec<ExpandedLine>, Vec<anyhow::Error>) {
+    let mut errors = Vec::new();
+    let mut symtab = Vec::new();
+    for (name, value) in defines {
+        match tokenize(value).and_then(|tokens| evaluate(&tokens, 0, &[])) {
+            Ok(number) if number.kind() == SymbolKind::Absolute && number.value >= 0 => symtab
+                .push(SymbolMapping::with_kind(
+                    name.to_uppercase(),
+                    number.value as usize,
+                    SymbolKind::Absolute,
+                )),
+            _ => errors.push(anyhow::anyhow!(
+                "error: invalid value `{}` for `-D {}`",
+                value,
+                name
+            )),
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut depth = 0;
+
+    for (i, line) in source.iter().enumerate() {
+        let active = blocks.last().is_none_or(|block| block.active);
+        let at =
+            |message: String| anyhow::anyhow!("error: {}\n{} | {}", message, line.location, line);
+
+        let (_, opcode, operand) =
+            fields(&line.text, &[], &MacroLibrary::new()).unwrap_or_default();
+        let directive = match opcode.to_uppercase() {
+            opcode if opcode == "MACRO" || opcode == "MEND" || depth == 0 => opcode,
+            _ => String::new(),
+        };
+        match directive.as_str() {
+            "IFDEF" | "IFNDEF" | "IF" => {
+                let operand = operand.unwrap_or_default();
+                let value = match directive.as_str() {
+                    _ if !active => false,
+                    "IFDEF" => symtab
+                        .iter()
+                        .any(|sym| sym.symbol == operand.to_uppercase()),
+                    "IFNDEF" => !symtab
+                        .iter()
+                        .any(|sym| sym.symbol == operand.to_uppercase()),
+                    _ => truth(operand, &symtab).unwrap_or_else(|error| {
+                        errors.push(at(error.to_string()));
+                        false
+                    }),
+                };
+
+                blocks.push(Block {
+                    outer: active,
+                    active: value,
+                    otherwise: false,
+                    start: i,
+                });
+            }
+            "ELSE" => match blocks.last_mut() {
+                Some(block) if !block.otherwise => {
+                    block.active = block.outer && !block.active;
+                    block.otherwise = true;
+                }
+                _ => errors.push(at("ELSE without IF".to_owned())),
+            },
+            "ENDIF" => {
+                if blocks.pop().is_none() {
+                    errors.push(at("ENDIF without IF".to_owned()));
+                }
+            }
+            _ => {
+                match directive.as_str() {
+                    "MACRO" => depth += 1,
+                    "MEND" => depth -= 1,
+                    _ => {}
+                }
+
+                if active {
+                    lines.push(line.clone());
+                }
+            }
+        }
+    }
+
+    for block in blocks {
+        let line = &source[block.start];
+        errors.push(anyhow::anyhow!(
+            "error: missing ENDIF\n{} | {}",
+            line.location,
+            line
+        ));
+    }
+
+    (lines, errors)
+}
+
+/// Whether `expr` is a true `(LHS OP RHS)` condition or a non-zero expression.
+fn truth(expr: &str, symtab: &[SymbolMapping]) -> anyhow::Result<bool> {
+    let value = |text: &str| -> anyhow::Result<isize> {
+        let value = evaluate(&tokenize(&text.to_uppercase())?, 0, symtab)?;
+        match value.kind() {
+            SymbolKind::Absolute => Ok(value.value),
+            _ => Err(anyhow::anyhow!("invalid condition `{}`", expr)),
+        }
+    };
+
+    if expr.split_whitespace().count() == 3 {
+        condition(expr, |text| {
+            Ok(value(text).map_or(text.to_owned(), |v| v.to_string()))
+        })
+    } else {
+        Ok(value(expr)? != 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::instructions::ErrorList;
+    use crate::preprocessor::LineKind;
+
+    fn lines(source: &[&str], defines: &[(&str, &str)]) -> anyhow::Result<Vec<String>> {
+        let source = ExpandedLine::numbered(source);
+        let defines = defines
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+
+        match resolve_conditionals(source, &defines) {
+            (lines, errors) if errors.is_empty() => {
+                Ok(lines.into_iter().map(|line| line.text).collect())
+            }
+            (_, errors) => Err(ErrorList::new(errors).into()),
+        }
+    }
+
+    #[test]
+    fn test_resolve_conditionals() {
+        let source = [
+            "\tIFDEF\tDEBUG\t. trace build",
+            "\tWD\t=X'05'",
+            "\tIF\t(LEVEL GE 2)\t. more tracing",
+            "\tLDA\tTRACE",
+            "\tENDIF",
+            "\tELSE",
+            "\tRSUB",
+            "\tENDIF",
+            "WAIT\tMACRO",
+            "\tIF\t(&X EQ 1)",
+            "\tENDIF",
+            "\tMEND",
+        ];
+        let definition = ["WAIT\tMACRO", "\tIF\t(&X EQ 1)", "\tENDIF", "\tMEND"];
+
+        assert_eq!(
+            lines(&source, &[("DEBUG", "1"), ("LEVEL", "2")]).unwrap(),
+            [&["\tWD\t=X'05'", "\tLDA\tTRACE"][..], &definition].concat()
+        );
+        assert_eq!(
+            lines(&source, &[("debug", "1"), ("LEVEL", "1")]).unwrap(),
+            [&["\tWD\t=X'05'"][..], &definition].concat()
+        );
+        assert_eq!(
+            lines(&source, &[]).unwrap(),
+            [&["\tRSUB"][..], &definition].concat()
+        );
+
+        assert_eq!(
+            lines(&["\tIF\tLEVEL-1", "\tRSUB", "\tENDIF"], &[("LEVEL", "1")]).unwrap(),
+            Vec::<String>::new()
+        );
+        assert!(lines(&["\tIF\tLEVEL"], &[]).is_err());
+        assert!(lines(&["\tIFDEF\tDEBUG"], &[]).is_err());
+        assert!(lines(&["\tELSE"], &[]).is_err());
+        assert!(lines(&["\tENDIF"], &[]).is_err());
+        assert!(lines(&[], &[("DEBUG", "ON")]).is_err());
+
+        let source = [
+            "\tELSE",
+            "\tIF\tLEVEL",
+            "\tRSUB",
+            "\tENDIF",
+            "\tENDIF",
+            "\tLDA\tZERO",
+        ];
+        let (lines, errors) = resolve_conditionals(ExpandedLine::numbered(&source), &[]);
+        assert_eq!(
+            lines,
+            vec![ExpandedLine::new(6, "\tLDA\tZERO", LineKind::Source)]
+        );
+        assert_eq!(
+            errors
+                .iter()
+                .map(|error| error.to_string())
+                .collect::<Vec<_>>(),
+            vec![
+                "error: ELSE without IF\n1 | \tELSE",
+                "error: undefined symbol `LEVEL`\n2 | \tIF\tLEVEL",
+                "error: ENDIF without IF\n5 | \tENDIF",
+            ]
+        );
+    }
+}